@@ -0,0 +1,277 @@
+use crate::graph::{Graph, GraphIdx, GraphMeta, NodePos, ReadOpts};
+use flate2::{CrcReader, CrcWriter};
+use petgraph::{
+    graph::NodeIndex,
+    visit::{EdgeRef, IntoEdgeReferences, NodeIndexable},
+};
+use std::{
+    fs::File,
+    io::{BufReader, BufWriter, Read, Result, Write},
+    path::Path,
+};
+use tracing::{error, info};
+
+/// Magic bytes at the start of graph cache files.
+const CACHE_MAGIC: &[u8; 8] = b"PRGRAPH\0";
+/// Version of the graph cache format (increase on any change to it).
+const CACHE_VERSION: u32 = 1;
+
+/// How the cached graph was built (weight and filters).
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct CacheInfo {
+    pub weight: String,
+    pub weight_filter: Option<String>,
+    pub sweep_filters: Vec<String>,
+}
+
+impl CacheInfo {
+    pub fn new(opts: &ReadOpts) -> Self {
+        Self {
+            weight: opts
+                .weight_expr
+                .clone()
+                .unwrap_or_else(|| opts.weight_field.clone()),
+            weight_filter: opts.weight_filter.clone(),
+            sweep_filters: opts.sweep_filters.clone(),
+        }
+    }
+}
+
+/// Save graph (with sweep bitmasks, input metadata and provenance) to a binary cache file.
+///
+/// The file has a magic string and format version, followed by the (little-endian) data and its CRC32 checksum.
+pub fn graph_save(
+    path: &Path,
+    graph: &Graph,
+    edges_sweep: &[u64],
+    meta: &GraphMeta,
+    info: &CacheInfo,
+) {
+    info!("Saving graph to cache file {:?}", path);
+    let file = File::create(path).expect("cannot create graph cache file");
+    cache_write(BufWriter::new(file), graph, edges_sweep, meta, info)
+        .expect("cannot write to graph cache file");
+}
+
+/// Load graph (with sweep bitmasks, input metadata and provenance) from a binary cache file.
+pub fn graph_load(path: &Path) -> (Graph, Vec<u64>, GraphMeta, CacheInfo) {
+    info!("Loading graph from cache file {:?}", path);
+    let file = File::open(path).expect("cannot open graph cache file");
+    cache_read(BufReader::new(file)).unwrap_or_else(|e| {
+        error!("cannot load graph cache file {:?}: {e}", path);
+        std::process::exit(-1);
+    })
+}
+
+fn cache_write<W: Write>(
+    mut writer: W,
+    graph: &Graph,
+    edges_sweep: &[u64],
+    meta: &GraphMeta,
+    info: &CacheInfo,
+) -> Result<()> {
+    writer.write_all(CACHE_MAGIC)?;
+    writer.write_all(&CACHE_VERSION.to_le_bytes())?;
+    let mut writer = CrcWriter::new(writer);
+
+    // Provenance
+    write_str(&mut writer, &info.weight)?;
+    write_strs(&mut writer, info.weight_filter.as_slice())?;
+    write_strs(&mut writer, &info.sweep_filters)?;
+    write_strs(&mut writer, &meta.header)?;
+
+    // Nodes (in index order, so that they can be referenced by position)
+    let nodes: Vec<NodeIndex<GraphIdx>> = graph.node_indices().collect();
+    let mut nodes_order = vec![0u64; graph.node_bound()];
+    for (pos, node) in nodes.iter().enumerate() {
+        nodes_order[node.index()] = pos as u64;
+    }
+    write_u64(&mut writer, nodes.len() as u64)?;
+    for node in &nodes {
+        write_str(&mut writer, &graph[*node])?;
+    }
+
+    // Edges (with sweep bitmasks, if any)
+    write_u64(&mut writer, graph.edge_count() as u64)?;
+    write_u64(&mut writer, !edges_sweep.is_empty() as u64)?;
+    for edge in graph.edge_references() {
+        write_u64(&mut writer, nodes_order[edge.source().index()])?;
+        write_u64(&mut writer, nodes_order[edge.target().index()])?;
+        writer.write_all(&edge.weight().to_le_bytes())?;
+        if !edges_sweep.is_empty() {
+            write_u64(&mut writer, edges_sweep[edge.id().index()])?;
+        }
+    }
+
+    // Node positions
+    let nodes_pos: Vec<(NodeIndex<GraphIdx>, &NodePos)> = nodes
+        .iter()
+        .filter_map(|node| meta.nodes_pos.get(&graph[*node]).map(|pos| (*node, pos)))
+        .collect();
+    write_u64(&mut writer, nodes_pos.len() as u64)?;
+    for (node, pos) in nodes_pos {
+        write_u64(&mut writer, nodes_order[node.index()])?;
+        write_str(&mut writer, &pos.chr)?;
+        write_u64(&mut writer, pos.bp)?;
+    }
+
+    let crc = writer.crc().sum();
+    let mut writer = writer.into_inner();
+    writer.write_all(&crc.to_le_bytes())?;
+    writer.flush()
+}
+
+fn cache_read<R: Read>(mut reader: R) -> Result<(Graph, Vec<u64>, GraphMeta, CacheInfo)> {
+    let invalid = |msg: String| std::io::Error::new(std::io::ErrorKind::InvalidData, msg);
+
+    let mut magic = [0u8; 8];
+    reader.read_exact(&mut magic)?;
+    if magic != *CACHE_MAGIC {
+        return Err(invalid("not a graph cache file".to_string()));
+    }
+    let version = read_u32(&mut reader)?;
+    if version != CACHE_VERSION {
+        return Err(invalid(format!(
+            "cache has format version {version}, but this version of prune_graph only reads version {CACHE_VERSION}; re-create it with '--save-graph'"
+        )));
+    }
+    let mut reader = CrcReader::new(reader);
+
+    // Provenance
+    let info = CacheInfo {
+        weight: read_str(&mut reader)?,
+        weight_filter: read_strs(&mut reader)?.pop(),
+        sweep_filters: read_strs(&mut reader)?,
+    };
+    let mut meta = GraphMeta {
+        header: read_strs(&mut reader)?,
+        ..Default::default()
+    };
+
+    // Nodes
+    let mut graph = Graph::default();
+    let n_nodes = read_u64(&mut reader)?;
+    let mut nodes = Vec::new();
+    for _ in 0..n_nodes {
+        nodes.push(graph.add_node(read_str(&mut reader)?));
+    }
+    let node = |pos: u64| {
+        nodes
+            .get(pos as usize)
+            .copied()
+            .ok_or_else(|| invalid(format!("invalid node {pos}")))
+    };
+
+    // Edges
+    let n_edges = read_u64(&mut reader)?;
+    let has_sweep = read_u64(&mut reader)? != 0;
+    let mut edges_sweep = Vec::new();
+    for _ in 0..n_edges {
+        let (a, b) = (node(read_u64(&mut reader)?)?, node(read_u64(&mut reader)?)?);
+        let mut weight = [0u8; 4];
+        reader.read_exact(&mut weight)?;
+        graph.add_edge(a, b, f32::from_le_bytes(weight));
+        if has_sweep {
+            edges_sweep.push(read_u64(&mut reader)?);
+        }
+    }
+
+    // Node positions
+    for _ in 0..read_u64(&mut reader)? {
+        let node = node(read_u64(&mut reader)?)?;
+        let chr = read_str(&mut reader)?;
+        let bp = read_u64(&mut reader)?;
+        meta.nodes_pos
+            .insert(graph[node].clone(), NodePos { chr, bp });
+    }
+
+    let crc = reader.crc().sum();
+    if read_u32(reader.get_mut())? != crc {
+        return Err(invalid("checksum mismatch (file is corrupted)".to_string()));
+    }
+
+    Ok((graph, edges_sweep, meta, info))
+}
+
+fn write_u64<W: Write>(writer: &mut W, x: u64) -> Result<()> {
+    writer.write_all(&x.to_le_bytes())
+}
+
+fn write_str<W: Write>(writer: &mut W, s: &str) -> Result<()> {
+    write_u64(writer, s.len() as u64)?;
+    writer.write_all(s.as_bytes())
+}
+
+fn write_strs<W: Write>(writer: &mut W, strs: &[String]) -> Result<()> {
+    write_u64(writer, strs.len() as u64)?;
+    strs.iter().try_for_each(|s| write_str(writer, s))
+}
+
+fn read_u32<R: Read>(reader: &mut R) -> Result<u32> {
+    let mut buf = [0u8; 4];
+    reader.read_exact(&mut buf)?;
+    Ok(u32::from_le_bytes(buf))
+}
+
+fn read_u64<R: Read>(reader: &mut R) -> Result<u64> {
+    let mut buf = [0u8; 8];
+    reader.read_exact(&mut buf)?;
+    Ok(u64::from_le_bytes(buf))
+}
+
+fn read_str<R: Read>(reader: &mut R) -> Result<String> {
+    let len = read_u64(reader)?;
+    let mut buf = Vec::new();
+    reader.take(len).read_to_end(&mut buf)?;
+    if buf.len() as u64 != len {
+        return Err(std::io::ErrorKind::UnexpectedEof.into());
+    }
+    String::from_utf8(buf).map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))
+}
+
+fn read_strs<R: Read>(reader: &mut R) -> Result<Vec<String>> {
+    (0..read_u64(reader)?).map(|_| read_str(reader)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::graph::graph_read;
+
+    #[test]
+    fn test_graph_cache() {
+        let input = "A\tB\t0.5\nB\tC\t0.3\nA\tB\t0.1\nC\tD\t0.9\n";
+        let opts = ReadOpts {
+            sweep_filters: vec!["column_3 > 0.4".to_string(), "column_3 > 0".to_string()],
+            duplicates: crate::graph::DupPolicy::Last,
+            ..Default::default()
+        };
+        let (graph, _graph_idx, edges_sweep, meta) = graph_read(input.as_bytes(), &opts);
+        let info = CacheInfo::new(&opts);
+
+        let mut cache = Vec::new();
+        cache_write(&mut cache, &graph, &edges_sweep, &meta, &info).unwrap();
+        let (graph_load, edges_sweep_load, meta_load, info_load) =
+            cache_read(cache.as_slice()).unwrap();
+        assert_eq!(graph_load.node_count(), 4);
+        assert_eq!(graph_load.edge_count(), 3);
+        assert_eq!(
+            graph_load.edge_weights().collect::<Vec<&f32>>(),
+            graph.edge_weights().collect::<Vec<&f32>>()
+        );
+        assert_eq!(edges_sweep_load, vec![0b10, 0b10, 0b11]);
+        assert_eq!(meta_load, meta);
+        assert_eq!(info_load, info);
+
+        // Corrupted and other versions
+        let mut cache_bad = cache.clone();
+        cache_bad[20] ^= 1;
+        assert!(cache_read(cache_bad.as_slice()).is_err());
+        let mut cache_bad = cache.clone();
+        cache_bad[8] = 99;
+        assert!(cache_read(cache_bad.as_slice())
+            .unwrap_err()
+            .to_string()
+            .contains("version 99"));
+    }
+}
@@ -0,0 +1,270 @@
+use crate::graph::InputFormat;
+use arrow_array::{RecordBatch, RecordBatchReader};
+use arrow_cast::display::{ArrayFormatter, FormatOptions};
+use arrow_schema::{ArrowError, SchemaRef};
+use parquet::arrow::{arrow_reader::ParquetRecordBatchReaderBuilder, ProjectionMask};
+use std::{
+    collections::BTreeSet,
+    fs::File,
+    io::{BufRead, Read, Write},
+    path::Path,
+};
+use tracing::{debug, error, info};
+
+/// Number of rows per record batch (Parquet).
+const BATCH_SIZE: usize = 64 * 1024;
+
+/// Reader of columnar (Arrow IPC or Parquet) files as tab-delimited text (with header).
+///
+/// Only the projected columns are decoded; null values are output as empty fields.
+/// Values that would change the delimited text (with a tab or newline, or starting with a quote) are an error.
+pub struct ColumnarReader {
+    batches: Box<dyn Iterator<Item = Result<RecordBatch, ArrowError>>>,
+    header: Vec<String>,
+    /// Number of rows read so far
+    n_rows: usize,
+    buf: Vec<u8>,
+    pos: usize,
+}
+
+impl ColumnarReader {
+    fn new(
+        schema: SchemaRef,
+        batches: Box<dyn Iterator<Item = Result<RecordBatch, ArrowError>>>,
+    ) -> Self {
+        let mut buf = Vec::new();
+        let header: Vec<String> = schema.fields().iter().map(|f| f.name().clone()).collect();
+        writeln!(buf, "{}", header.join("\t")).expect("cannot write header");
+        Self {
+            batches,
+            header,
+            n_rows: 0,
+            buf,
+            pos: 0,
+        }
+    }
+
+    /// Format next record batch into the buffer, returning the number of rows.
+    fn fill(&mut self) -> std::io::Result<usize> {
+        let Some(batch) = self.batches.next() else {
+            return Ok(0);
+        };
+        let batch = batch.map_err(std::io::Error::other)?;
+        let options = FormatOptions::new().with_null("");
+        let formatters = batch
+            .columns()
+            .iter()
+            .map(|col| ArrayFormatter::try_new(col.as_ref(), &options))
+            .collect::<Result<Vec<ArrayFormatter>, ArrowError>>()
+            .map_err(std::io::Error::other)?;
+
+        self.buf.clear();
+        self.pos = 0;
+        for row in 0..batch.num_rows() {
+            self.n_rows += 1;
+            for (i, formatter) in formatters.iter().enumerate() {
+                if i > 0 {
+                    self.buf.push(b'\t');
+                }
+                let start = self.buf.len();
+                write!(self.buf, "{}", formatter.value(row))?;
+                let value = &self.buf[start..];
+                if value.starts_with(b"\"")
+                    || value.iter().any(|c| matches!(c, b'\t' | b'\n' | b'\r'))
+                {
+                    return Err(std::io::Error::other(format!(
+                        "value {0:?} in column '{1}' (row {2}) has a tab or newline, or starts with a quote",
+                        String::from_utf8_lossy(value),
+                        self.header[i],
+                        self.n_rows
+                    )));
+                }
+            }
+            self.buf.push(b'\n');
+        }
+        Ok(batch.num_rows().max(1))
+    }
+}
+
+impl Read for ColumnarReader {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let data = self.fill_buf()?;
+        let n = buf.len().min(data.len());
+        buf[..n].copy_from_slice(&data[..n]);
+        self.consume(n);
+        Ok(n)
+    }
+}
+
+impl BufRead for ColumnarReader {
+    fn fill_buf(&mut self) -> std::io::Result<&[u8]> {
+        while self.pos == self.buf.len() {
+            if self.fill()? == 0 {
+                break;
+            }
+        }
+        Ok(&self.buf[self.pos..])
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.pos += amt;
+    }
+}
+
+/// Indices of the columns to decode: those in `columns` plus, if `columns_first`, the first two (default node columns).
+fn projection(schema: &SchemaRef, columns: &BTreeSet<String>, columns_first: bool) -> Vec<usize> {
+    let projection: Vec<usize> = schema
+        .fields()
+        .iter()
+        .enumerate()
+        .filter(|(i, field)| (columns_first && *i < 2) || columns.contains(field.name()))
+        .map(|(i, _)| i)
+        .collect();
+    debug!(
+        "Decoding columns: {:?}",
+        projection
+            .iter()
+            .map(|i| schema.field(*i).name())
+            .collect::<Vec<&String>>()
+    );
+    projection
+}
+
+/// Open an Arrow IPC or Parquet file, decoding only the columns used (see `ReadOpts::columns`).
+pub fn columnar_open(
+    path: &Path,
+    format: InputFormat,
+    columns: &BTreeSet<String>,
+    columns_first: bool,
+) -> ColumnarReader {
+    info!("Reading input {:?} file {:?}", format, path);
+    let file = File::open(path).expect("cannot open input file");
+    let fail = |e: &dyn std::fmt::Display| -> ! {
+        error!("cannot read input file {:?}: {e}", path);
+        std::process::exit(-1);
+    };
+
+    match format {
+        InputFormat::Parquet => {
+            let builder =
+                ParquetRecordBatchReaderBuilder::try_new(file).unwrap_or_else(|e| fail(&e));
+            let projection = projection(builder.schema(), columns, columns_first);
+            let mask = ProjectionMask::roots(builder.parquet_schema(), projection);
+            let reader = builder
+                .with_projection(mask)
+                .with_batch_size(BATCH_SIZE)
+                .build()
+                .unwrap_or_else(|e| fail(&e));
+            ColumnarReader::new(reader.schema(), Box::new(reader))
+        }
+        InputFormat::Arrow => {
+            let reader = arrow_ipc::reader::FileReader::try_new_buffered(file, None)
+                .unwrap_or_else(|e| fail(&e));
+            let schema = reader.schema();
+            let projection = projection(&schema, columns, columns_first);
+            let schema = schema.project(&projection).unwrap_or_else(|e| fail(&e));
+            let file = File::open(path).expect("cannot open input file");
+            let reader = arrow_ipc::reader::FileReader::try_new_buffered(file, Some(projection))
+                .unwrap_or_else(|e| fail(&e));
+            ColumnarReader::new(std::sync::Arc::new(schema), Box::new(reader))
+        }
+        _ => unreachable!("not a columnar format: {format:?}"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::graph::{graph_read, ReadOpts};
+    use arrow_array::{ArrayRef, Float64Array, Int64Array, StringArray};
+    use std::sync::Arc;
+
+    #[test]
+    fn test_columnar_open() {
+        let batch = RecordBatch::try_from_iter([
+            (
+                "site1",
+                Arc::new(StringArray::from(vec!["A", "B", "C"])) as ArrayRef,
+            ),
+            (
+                "site2",
+                Arc::new(StringArray::from(vec!["B", "C", "D"])) as ArrayRef,
+            ),
+            (
+                "dist",
+                Arc::new(Int64Array::from(vec![100, 200, 300])) as ArrayRef,
+            ),
+            (
+                "note",
+                Arc::new(StringArray::from(vec!["x", "y", "z"])) as ArrayRef,
+            ),
+            (
+                "r2",
+                Arc::new(Float64Array::from(vec![0.5, 0.25, 0.75])) as ArrayRef,
+            ),
+        ])
+        .unwrap();
+
+        let dir = std::env::temp_dir();
+        let path_parquet = dir.join(format!("prune_graph_test_{}.parquet", std::process::id()));
+        let mut writer = parquet::arrow::ArrowWriter::try_new(
+            File::create(&path_parquet).unwrap(),
+            batch.schema(),
+            None,
+        )
+        .unwrap();
+        writer.write(&batch).unwrap();
+        writer.close().unwrap();
+
+        let path_arrow = dir.join(format!("prune_graph_test_{}.arrow", std::process::id()));
+        let mut writer = arrow_ipc::writer::FileWriter::try_new(
+            File::create(&path_arrow).unwrap(),
+            &batch.schema(),
+        )
+        .unwrap();
+        writer.write(&batch).unwrap();
+        writer.finish().unwrap();
+
+        let opts = InputFormat::Parquet.read_opts(ReadOpts {
+            weight_field: "r2".to_string(),
+            weight_filter: Some("dist < 250".to_string()),
+            ..Default::default()
+        });
+        for (path, format) in [
+            (&path_parquet, InputFormat::Parquet),
+            (&path_arrow, InputFormat::Arrow),
+        ] {
+            let mut reader = columnar_open(path, format, &opts.columns(), true);
+            let mut header = String::new();
+            reader.read_line(&mut header).unwrap();
+            assert_eq!(header, "site1\tsite2\tdist\tr2\n");
+
+            let (graph, graph_idx, _edges_sweep, _meta) =
+                graph_read(columnar_open(path, format, &opts.columns(), true), &opts);
+            assert_eq!(graph.node_count(), 4);
+            assert_eq!(graph.edge_count(), 2);
+            let edge = graph.find_edge(graph_idx["B"], graph_idx["C"]).unwrap();
+            assert_eq!(graph[edge], 0.25);
+            std::fs::remove_file(path).unwrap();
+        }
+
+        // Values that would be split (or unquoted) as text are rejected
+        let read = |name: &str, values: Vec<&str>| {
+            let batch = RecordBatch::try_from_iter([(
+                name,
+                Arc::new(StringArray::from(values)) as ArrayRef,
+            )])
+            .unwrap();
+            ColumnarReader::new(batch.schema(), Box::new(std::iter::once(Ok(batch))))
+                .read_to_string(&mut String::new())
+                .map_err(|e| e.to_string())
+        };
+        assert!(read("note", vec!["x y", "z"]).is_ok());
+        assert!(read("site1", vec!["A", "B\tC"])
+            .unwrap_err()
+            .contains("column 'site1' (row 2)"));
+        assert!(read("note", vec!["\"x\"", "y"])
+            .unwrap_err()
+            .contains("column 'note' (row 1)"));
+    }
+}
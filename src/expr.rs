@@ -0,0 +1,348 @@
+use fasteval::{compiler::IC, Compiler, EvalNamespace, Evaler, Instruction, InstructionI, Slab};
+use std::{
+    collections::BTreeSet,
+    hash::{DefaultHasher, Hash, Hasher},
+};
+
+/// Prefix of the variables that replace string literals and dotted names.
+const ALIAS_PREFIX: &str = "__alias_";
+
+/// What a variable replaced in the expression stands for.
+enum Alias {
+    /// String literal (with its value)
+    Value(String, f64),
+    /// Variable with dots in its name (e.g. "node1.maf"), not supported by 'fasteval'
+    Var(String),
+}
+
+/// Expression parsed and compiled once, to be evaluated many times.
+///
+/// String literals (in single or double quotes) are replaced by their value (see `str_value`) so that they can be compared to string values, and variable names can have dots (e.g. "node1.maf").
+/// Since strings are only comparable for (in)equality, non-numeric string literals can only be operands of '==' or '!='.
+pub struct Expr {
+    slab: Slab,
+    instr: Instruction,
+    aliases: Vec<(String, Alias)>,
+}
+
+impl Expr {
+    pub fn new(expr: &str) -> Result<Self, fasteval::Error> {
+        let (expr, aliases) = aliases_replace(expr)?;
+        let mut slab = Slab::new();
+        let instr = fasteval::Parser::new()
+            .parse(&expr, &mut slab.ps)?
+            .from(&slab.ps)
+            .compile(&slab.ps, &mut slab.cs);
+        let expr = Self {
+            slab,
+            instr,
+            aliases,
+        };
+        let vars_numeric = expr.vars_numeric_raw();
+        for (var, alias) in &expr.aliases {
+            if let Alias::Value(literal, _) = alias {
+                if vars_numeric.contains(var) && literal.parse::<f64>().is_err() {
+                    return Err(fasteval::Error::WrongArgs(format!(
+                        "string literal '{literal}' can only be compared with '==' or '!='"
+                    )));
+                }
+            }
+        }
+        Ok(expr)
+    }
+
+    /// Variables used other than as operands of '==' or '!=', so that their values must be numeric.
+    pub fn vars_numeric(&self) -> BTreeSet<String> {
+        self.vars_numeric_raw()
+            .into_iter()
+            .filter_map(|var| match self.aliases.iter().find(|(n, _)| *n == var) {
+                Some((_, Alias::Value(..))) => None,
+                Some((_, Alias::Var(name))) => Some(name.clone()),
+                None => Some(var),
+            })
+            .collect()
+    }
+
+    /// Variables (including aliases) used other than as operands of '==' or '!='.
+    fn vars_numeric_raw(&self) -> BTreeSet<String> {
+        let mut vars = BTreeSet::new();
+        instr_vars_numeric(&self.instr, &self.slab, false, &mut vars);
+        vars
+    }
+
+    /// Variables (and custom functions) used in the expression.
+    pub fn vars(&self) -> BTreeSet<String> {
+        self.instr
+            .var_names(&self.slab)
+            .into_iter()
+            .filter_map(|var| match self.aliases.iter().find(|(n, _)| *n == var) {
+                Some((_, Alias::Value(..))) => None,
+                Some((_, Alias::Var(name))) => Some(name.clone()),
+                None => Some(var),
+            })
+            .collect()
+    }
+
+    pub fn eval(&self, ns: &mut impl EvalNamespace) -> Result<f64, fasteval::Error> {
+        if self.aliases.is_empty() {
+            return self.instr.eval(&self.slab, ns);
+        }
+        let mut ns = |name: &str, args: Vec<f64>| match self.aliases.iter().find(|(n, _)| n == name)
+        {
+            Some((_, Alias::Value(_, value))) => Some(*value),
+            Some((_, Alias::Var(name))) => ns.lookup(name, args, &mut String::new()),
+            None => ns.lookup(name, args, &mut String::new()),
+        };
+        self.instr.eval(&self.slab, &mut ns)
+    }
+
+    /// Check that all variables used in the expression are available, suggesting the closest one otherwise.
+    pub fn check(&self, available: &[&str]) -> Result<(), String> {
+        for var in self.vars() {
+            if !available.contains(&var.as_str()) {
+                return Err(match suggest(&var, available) {
+                    Some(suggestion) => {
+                        format!("unknown variable '{var}' (did you mean '{suggestion}'?)")
+                    }
+                    None => format!(
+                        "unknown variable '{var}' (available: {})",
+                        available.join(", ")
+                    ),
+                });
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Collect variables used by an instruction other than as (direct) operands of '==' or '!=' (if `eq`).
+fn instr_vars_numeric(instr: &Instruction, slab: &Slab, eq: bool, vars: &mut BTreeSet<String>) {
+    let visit_i = |i: &InstructionI, vars: &mut BTreeSet<String>| {
+        instr_vars_numeric(slab.cs.get_instr(*i), slab, false, vars)
+    };
+    let visit_ic = |ic: &IC, eq: bool, vars: &mut BTreeSet<String>| {
+        if let IC::I(i) = ic {
+            instr_vars_numeric(slab.cs.get_instr(*i), slab, eq, vars)
+        }
+    };
+    match instr {
+        Instruction::IVar(name) => {
+            if !eq {
+                vars.insert(name.clone());
+            }
+        }
+        Instruction::IEQ(a, b) | Instruction::INE(a, b) => {
+            visit_ic(a, true, vars);
+            visit_ic(b, true, vars);
+        }
+        Instruction::INeg(i)
+        | Instruction::INot(i)
+        | Instruction::IInv(i)
+        | Instruction::IFuncInt(i)
+        | Instruction::IFuncCeil(i)
+        | Instruction::IFuncFloor(i)
+        | Instruction::IFuncAbs(i)
+        | Instruction::IFuncSign(i)
+        | Instruction::IFuncSin(i)
+        | Instruction::IFuncCos(i)
+        | Instruction::IFuncTan(i)
+        | Instruction::IFuncASin(i)
+        | Instruction::IFuncACos(i)
+        | Instruction::IFuncATan(i)
+        | Instruction::IFuncSinH(i)
+        | Instruction::IFuncCosH(i)
+        | Instruction::IFuncTanH(i)
+        | Instruction::IFuncASinH(i)
+        | Instruction::IFuncACosH(i)
+        | Instruction::IFuncATanH(i) => visit_i(i, vars),
+        Instruction::IAdd(i, c)
+        | Instruction::IMul(i, c)
+        | Instruction::IOR(i, c)
+        | Instruction::IAND(i, c)
+        | Instruction::IFuncMin(i, c)
+        | Instruction::IFuncMax(i, c) => {
+            visit_i(i, vars);
+            visit_ic(c, false, vars);
+        }
+        Instruction::IMod {
+            dividend: a,
+            divisor: b,
+        }
+        | Instruction::IExp { base: a, power: b }
+        | Instruction::ILT(a, b)
+        | Instruction::ILTE(a, b)
+        | Instruction::IGTE(a, b)
+        | Instruction::IGT(a, b)
+        | Instruction::IFuncLog { base: a, of: b }
+        | Instruction::IFuncRound { modulus: a, of: b } => {
+            visit_ic(a, false, vars);
+            visit_ic(b, false, vars);
+        }
+        Instruction::IFunc { args, .. } => {
+            for arg in args {
+                visit_ic(arg, false, vars);
+            }
+        }
+        Instruction::IConst(_) | Instruction::IPrintFunc(_) => {}
+    }
+}
+
+/// Value of a string: its number (if numeric) or a hash of the string, so that strings can be compared for (in)equality.
+pub fn str_value(s: &str) -> f64 {
+    s.parse::<f64>().unwrap_or_else(|_| str_hash(s))
+}
+
+/// Hash of a string, as a float (exactly representable integer).
+pub fn str_hash(s: &str) -> f64 {
+    let mut hasher = DefaultHasher::new();
+    s.hash(&mut hasher);
+    (hasher.finish() >> 11) as f64
+}
+
+/// Replace string literals (in single or double quotes) and dotted variable names by aliases, returning the new expression and the aliases.
+fn aliases_replace(expr: &str) -> Result<(String, Vec<(String, Alias)>), fasteval::Error> {
+    let is_name = |c: char| c.is_ascii_alphanumeric() || c == '_';
+    let mut expr_new = String::with_capacity(expr.len());
+    let mut aliases = Vec::new();
+    let mut chars = expr.chars().peekable();
+    let mut prev = ' ';
+    while let Some(c) = chars.next() {
+        let alias = if c == '"' || c == '\'' {
+            // String literal
+            let mut literal = String::new();
+            let mut closed = false;
+            for x in chars.by_ref() {
+                if x == c {
+                    closed = true;
+                    break;
+                }
+                literal.push(x);
+            }
+            if !closed {
+                return Err(fasteval::Error::EofWhileParsing(
+                    "string literal".to_string(),
+                ));
+            }
+            Alias::Value(literal.clone(), str_value(&literal))
+        } else if (c.is_ascii_alphabetic() || c == '_') && !is_name(prev) && prev != '.' {
+            // Variable name (possibly with dots)
+            let mut name = c.to_string();
+            while let Some(&x) = chars.peek() {
+                if is_name(x) || (x == '.' && name.ends_with(is_name)) {
+                    name.push(x);
+                    chars.next();
+                } else {
+                    break;
+                }
+            }
+            prev = name.chars().last().unwrap_or(c);
+            if !name.contains('.') || name.ends_with('.') {
+                expr_new.push_str(&name);
+                continue;
+            }
+            Alias::Var(name)
+        } else {
+            expr_new.push(c);
+            prev = c;
+            continue;
+        };
+        let var = format!("{ALIAS_PREFIX}{}", aliases.len());
+        expr_new.push_str(&var);
+        aliases.push((var, alias));
+        prev = ' ';
+    }
+    Ok((expr_new, aliases))
+}
+
+/// Closest name to `name` (if similar enough, ignoring case), to suggest on typos.
+pub fn suggest<'a>(name: &str, available: &[&'a str]) -> Option<&'a str> {
+    let name = name.to_lowercase();
+    available
+        .iter()
+        .map(|candidate| (candidate.to_lowercase(), *candidate))
+        .filter(|(candidate, _)| {
+            strsim::jaro_winkler(&name, candidate) > 0.8
+                || strsim::osa_distance(&name, candidate) <= (name.len() / 4).max(1)
+        })
+        .max_by(|a, b| {
+            strsim::jaro_winkler(&name, &a.0).total_cmp(&strsim::jaro_winkler(&name, &b.0))
+        })
+        .map(|(_, candidate)| candidate)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_expr_check() {
+        let expr = Expr::new("r2 > 0.2 && dist < 1000").unwrap();
+        assert!(expr.check(&["r2", "dist", "D"]).is_ok());
+        assert_eq!(
+            expr.check(&["R2", "distance"]).unwrap_err(),
+            "unknown variable 'dist' (did you mean 'distance'?)"
+        );
+        assert_eq!(
+            expr.check(&["r2", "D"]).unwrap_err(),
+            "unknown variable 'dist' (available: r2, D)"
+        );
+        assert_eq!(suggest("r_2", &["r2", "D"]), Some("r2"));
+    }
+
+    #[test]
+    fn test_expr_strings() {
+        let expr = Expr::new("type != \"indel\" && chr_a == chr_b && r2 > 0.2").unwrap();
+        assert_eq!(
+            expr.vars(),
+            BTreeSet::from(["chr_a", "chr_b", "r2", "type"].map(String::from))
+        );
+        let eval = |fields: [&str; 4]| {
+            expr.eval(&mut |name: &str, _args: Vec<f64>| {
+                ["type", "chr_a", "chr_b", "r2"]
+                    .iter()
+                    .position(|n| *n == name)
+                    .map(|i| str_value(fields[i]))
+            })
+            .unwrap()
+        };
+        assert_eq!(eval(["snp", "chrX", "chrX", "0.5"]), 1.0);
+        assert_eq!(eval(["indel", "chrX", "chrX", "0.5"]), 0.0);
+        assert_eq!(eval(["snp", "chrX", "chr1", "0.5"]), 0.0);
+        assert_eq!(eval(["snp", "2", "2", "0.5"]), 1.0);
+        assert_eq!(Expr::new("x == 'a'").unwrap().vars().len(), 1);
+        assert!(Expr::new("x == \"a").is_err());
+
+        // Strings can only be compared for (in)equality
+        assert_eq!(expr.vars_numeric(), BTreeSet::from(["r2".to_string()]));
+        assert_eq!(
+            Expr::new("abs(x - y) < 1 || x == (y + 1)")
+                .unwrap()
+                .vars_numeric(),
+            BTreeSet::from(["x", "y"].map(String::from))
+        );
+        assert!(Expr::new("x < 'a'").is_err());
+        assert!(Expr::new("abs('a') == x").is_err());
+        assert!(Expr::new("x < '2'").is_ok());
+    }
+
+    #[test]
+    fn test_expr_dotted() {
+        let expr =
+            Expr::new("abs(node1.pos - node2.pos) < 1e3 && node1.maf > 0.05 && r2 > 0.2").unwrap();
+        assert_eq!(
+            expr.vars(),
+            BTreeSet::from(["node1.maf", "node1.pos", "node2.pos", "r2"].map(String::from))
+        );
+        let value = expr
+            .eval(&mut |name: &str, _args: Vec<f64>| match name {
+                "node1.pos" => Some(1500.0),
+                "node2.pos" => Some(1000.0),
+                "node1.maf" => Some(0.1),
+                "r2" => Some(0.5),
+                _ => None,
+            })
+            .unwrap();
+        assert_eq!(value, 1.0);
+        assert_eq!(suggest("r2", &["SNP_A", "R2", "DP"]), Some("R2"));
+    }
+}
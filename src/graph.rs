@@ -1,41 +1,1175 @@
+use crate::expr::Expr;
+use crate::input::{InputFiles, LineLoc};
 use indicatif::ProgressStyle;
 use petgraph::{
-    stable_graph::{NodeIndex, StableGraph},
+    algo::kosaraju_scc,
+    stable_graph::{EdgeIndex, NodeIndex, StableGraph},
+    visit::{EdgeIndexable, EdgeRef, IntoEdgeReferences},
     Undirected,
 };
+use rayon::iter::IndexedParallelIterator;
 use rayon::iter::IntoParallelRefIterator;
 use rayon::iter::ParallelIterator;
 use std::{
-    collections::HashMap,
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
     fs::File,
     io::{BufRead, BufReader},
-    path::PathBuf,
+    path::{Path, PathBuf},
 };
-use tracing::{debug, enabled, error, info_span, trace, warn, Level};
+use tracing::{debug, enabled, error, info, info_span, trace, warn, Level};
 use tracing_indicatif::span_ext::IndicatifSpanExt;
 #[cfg(not(feature = "large_graph"))]
-type GraphIdx = u32;
+pub type GraphIdx = u32;
 #[cfg(feature = "large_graph")]
-type GraphIdx = usize;
+pub type GraphIdx = usize;
+pub type Graph = StableGraph<String, f32, Undirected, GraphIdx>;
 
+/// Policy for edges present more than once in the input (e.g. A-B and B-A).
+#[derive(clap::ValueEnum, Clone, Copy, Debug, Default, PartialEq)]
+pub enum DupPolicy {
+    /// Keep all occurrences (as separate edges)
+    #[default]
+    Keep,
+    /// Abort with an error
+    Error,
+    /// Keep first occurrence
+    First,
+    /// Keep last occurrence
+    Last,
+    /// Keep maximum weight
+    Max,
+    /// Keep minimum weight
+    Min,
+    /// Average weights
+    Mean,
+    /// Sum weights
+    Sum,
+}
+
+/// Policy for self-loops (e.g. A-A).
+#[derive(clap::ValueEnum, Clone, Copy, Debug, Default, PartialEq)]
+pub enum SelfLoopPolicy {
+    /// Abort with an error
+    Error,
+    /// Skip edge
+    #[default]
+    Skip,
+    /// Keep edge (node will always be pruned)
+    Keep,
+}
+
+/// Policy for edges with negative weights.
+#[derive(clap::ValueEnum, Clone, Copy, Debug, Default, PartialEq)]
+pub enum NegativePolicy {
+    /// Keep weight as is
+    #[default]
+    Keep,
+    /// Use absolute value
+    Abs,
+    /// Clamp weight to zero
+    Zero,
+    /// Abort with an error
+    Error,
+}
+
+/// Policy for non-finite values (NaN and infinity) in the columns used by the weight or filters.
+#[derive(clap::ValueEnum, Clone, Copy, Debug, Default, PartialEq)]
+pub enum NonFinitePolicy {
+    /// Skip edges with NaN weight, and keep infinite values
+    #[default]
+    Keep,
+    /// Skip edge
+    Skip,
+    /// Cap infinite values (skip edges with NaN)
+    Cap,
+    /// Abort with an error
+    Error,
+}
+
+/// Policy for rows with missing values (see `ReadOpts::missing_values`) in the columns used by the weight or filters.
+#[derive(clap::ValueEnum, Clone, Copy, Debug, Default, PartialEq)]
+pub enum MissingPolicy {
+    /// Skip row
+    #[default]
+    Skip,
+    /// Treat missing values as 0
+    Zero,
+    /// Abort with an error
+    Error,
+}
+
+/// Policy for rows that cannot be parsed (wrong number of fields or non-numeric weight values).
+#[derive(clap::ValueEnum, Clone, Copy, Debug, Default, PartialEq)]
+pub enum InvalidPolicy {
+    /// Abort with an error
+    #[default]
+    Error,
+    /// Skip row
+    Skip,
+}
+
+/// Reason for a row to be skipped while reading.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+enum SkipReason {
+    Missing,
+    Invalid,
+    NonFinite,
+    Nan,
+    SelfLoop,
+}
+
+impl std::fmt::Display for SkipReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Self::Missing => write!(f, "with missing values"),
+            Self::Invalid => write!(f, "invalid"),
+            Self::NonFinite => write!(f, "with non-finite values"),
+            Self::Nan => write!(f, "with NaN weight"),
+            Self::SelfLoop => write!(f, "self-loop(s)"),
+        }
+    }
+}
+
+/// How to combine edge weights across layers (e.g. populations).
+#[derive(clap::ValueEnum, Clone, Copy, Debug, Default, PartialEq)]
+pub enum LayerCombine {
+    /// Highest weight (linked in any layer)
+    #[default]
+    Max,
+    /// Mean weight
+    Mean,
+    /// Lowest weight (linked in all layers)
+    Min,
+    /// Mean weight, weighted by each layer's sample size
+    WeightedMean,
+}
+
+/// Field delimiter of the input file.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Delimiter {
+    /// Detect from the first line (tab, comma or whitespace)
+    #[default]
+    Auto,
+    /// Any run of whitespace (leading and trailing whitespace is ignored)
+    Whitespace,
+    /// Single character (fields can be quoted, CSV-style)
+    Char(char),
+}
+
+impl std::str::FromStr for Delimiter {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "auto" => Ok(Self::Auto),
+            "whitespace" | "space" => Ok(Self::Whitespace),
+            "tab" | "\\t" => Ok(Self::Char('\t')),
+            "comma" => Ok(Self::Char(',')),
+            _ if s.chars().count() == 1 => Ok(Self::Char(s.chars().next().unwrap())),
+            _ => Err(format!(
+                "invalid delimiter '{s}' (use 'auto', 'tab', 'comma', 'whitespace' or a single character)"
+            )),
+        }
+    }
+}
+
+impl std::fmt::Display for Delimiter {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Self::Auto => write!(f, "auto"),
+            Self::Whitespace => write!(f, "whitespace"),
+            Self::Char('\t') => write!(f, "tab"),
+            Self::Char(',') => write!(f, "comma"),
+            Self::Char(c) => write!(f, "'{c}'"),
+        }
+    }
+}
+
+impl Delimiter {
+    /// Detect delimiter from a line (tab, then comma, then whitespace).
+    pub fn detect(line: &str) -> Self {
+        if line.contains('\t') {
+            Self::Char('\t')
+        } else if line.contains(',') {
+            Self::Char(',')
+        } else {
+            Self::Whitespace
+        }
+    }
+
+    /// Split a line into fields.
+    ///
+    /// With a single character delimiter, fields enclosed in double quotes can contain the delimiter and escaped (doubled) quotes.
+    pub fn split(&self, line: &str) -> Vec<String> {
+        match self {
+            Self::Auto => Self::detect(line).split(line),
+            Self::Whitespace => line.split_whitespace().map(str::to_string).collect(),
+            Self::Char(delim) if !line.contains('"') => {
+                line.split(*delim).map(str::to_string).collect()
+            }
+            Self::Char(delim) => {
+                let mut fields = Vec::new();
+                let mut field = String::new();
+                let mut in_quotes = false;
+                let mut chars = line.chars().peekable();
+                while let Some(c) = chars.next() {
+                    if in_quotes {
+                        if c == '"' && chars.peek() == Some(&'"') {
+                            field.push('"');
+                            chars.next();
+                        } else if c == '"' {
+                            in_quotes = false;
+                        } else {
+                            field.push(c);
+                        }
+                    } else if c == '"' && field.is_empty() {
+                        in_quotes = true;
+                    } else if c == *delim {
+                        fields.push(std::mem::take(&mut field));
+                    } else {
+                        field.push(c);
+                    }
+                }
+                fields.push(field);
+                fields
+            }
+        }
+    }
+}
+
+/// Input file format.
+#[derive(clap::ValueEnum, Clone, Copy, Debug, Default, PartialEq)]
+pub enum InputFormat {
+    /// Detect from file name (Arrow, Parquet and MatrixMarket extensions, otherwise TSV)
+    #[default]
+    Auto,
+    /// Delimited text file with one edge per line
+    Tsv,
+    /// PLINK '--r2' output ('CHR_A BP_A SNP_A CHR_B BP_B SNP_B R2', whitespace-padded)
+    Plink,
+    /// Apache Arrow IPC file (only the columns used are read)
+    Arrow,
+    /// Apache Parquet file (only the columns used are read)
+    Parquet,
+    /// Square or lower-triangular LD matrix (one row per site, see '--matrix-sites')
+    Matrix,
+    /// MatrixMarket coordinate file (e.g. from scipy or R's Matrix package, see '--matrix-sites')
+    Mtx,
+}
+
+impl InputFormat {
+    /// Detect format from the input file name (STDIN is TSV).
+    pub fn detect(path: Option<&Path>) -> Self {
+        match path.and_then(|p| p.file_name()).and_then(|f| f.to_str()) {
+            Some(f) if f.ends_with(".arrow") || f.ends_with(".feather") || f.ends_with(".ipc") => {
+                Self::Arrow
+            }
+            Some(f) if f.ends_with(".parquet") || f.ends_with(".pq") => Self::Parquet,
+            Some(f) if f.ends_with(".mtx") || f.ends_with(".mtx.gz") => Self::Mtx,
+            _ => Self::Tsv,
+        }
+    }
+
+    /// Adjust read options to the format (explicitly set options are kept).
+    pub fn read_opts(&self, opts: ReadOpts) -> ReadOpts {
+        match self {
+            Self::Auto | Self::Tsv => opts,
+            // Columnar and MatrixMarket files are read as tab-delimited text with header
+            Self::Arrow | Self::Parquet | Self::Mtx => ReadOpts {
+                has_header: true,
+                delimiter: Delimiter::Char('\t'),
+                ..opts
+            },
+            // Matrix cells are read as edges with header ("site1", "site2" and "r2")
+            Self::Matrix => ReadOpts {
+                has_header: true,
+                ..opts
+            },
+            Self::Plink => ReadOpts {
+                has_header: true,
+                delimiter: if opts.delimiter == Delimiter::Auto {
+                    Delimiter::Whitespace
+                } else {
+                    opts.delimiter
+                },
+                node_fields: opts
+                    .node_fields
+                    .or_else(|| Some(vec!["SNP_A".to_string(), "SNP_B".to_string()])),
+                pos_fields: opts
+                    .pos_fields
+                    .or_else(|| Some(vec!["CHR_A:BP_A".to_string(), "CHR_B:BP_B".to_string()])),
+                ..opts
+            },
+        }
+    }
+
+    /// Default weight field.
+    pub fn weight_field(&self) -> &str {
+        match self {
+            Self::Auto | Self::Tsv | Self::Arrow | Self::Parquet => "column_3",
+            Self::Plink => "R2",
+            Self::Matrix | Self::Mtx => "r2",
+        }
+    }
+}
+
+/// Input presets for common LD tools.
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum Preset {
+    /// ngsLD output (with header), using 'r2' as weight
+    Ngsld,
+    /// PLINK '--r2' output, using 'R2' as weight
+    Plink,
+}
+
+/// Input settings of a preset.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PresetOpts {
+    pub format: InputFormat,
+    pub has_header: bool,
+    pub node_fields: Option<[&'static str; 2]>,
+    pub weight_field: &'static str,
+    pub weight_filter: Option<&'static str>,
+}
+
+impl Preset {
+    /// Settings of each preset (explicit options take precedence).
+    pub fn opts(&self) -> PresetOpts {
+        match self {
+            Self::Ngsld => PresetOpts {
+                format: InputFormat::Tsv,
+                has_header: true,
+                node_fields: Some(["site1", "site2"]),
+                weight_field: "r2",
+                weight_filter: Some("r2 > 0.2"),
+            },
+            Self::Plink => PresetOpts {
+                format: InputFormat::Plink,
+                has_header: true,
+                node_fields: Some(["SNP_A", "SNP_B"]),
+                weight_field: "R2",
+                weight_filter: Some("R2 > 0.2"),
+            },
+        }
+    }
+}
+
+/// Position of a node (chromosome and base-pair).
+#[derive(Clone, Debug, PartialEq)]
+pub struct NodePos {
+    pub chr: String,
+    pub bp: u64,
+}
+pub type NodesPos = HashMap<String, NodePos>;
+
+/// Input metadata, kept along with the graph.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct GraphMeta {
+    /// Input header (or column names, if input has no header)
+    pub header: Vec<String>,
+    /// Position of each node (if position fields are provided)
+    pub nodes_pos: NodesPos,
+}
+
+/// Options to read edges into a graph.
+///
+/// Node IDs are taken from the first two columns or, if provided, from `node_fields` (two column names, each of which can be a composite of columns separated by ':').
+/// If `pos_fields` are provided (two pairs of chromosome and base-pair columns, e.g. "CHR_A:BP_A"), the position of each node is also kept.
+/// For LD matrix (dense or MatrixMarket) input, `matrix_sites` is the file with the sites of its rows/columns.
+/// Edge weight is taken from `weight_field` or, if provided, calculated from `weight_expr`.
+/// If `sweep_filters` are provided, an edge is added if it passes at least one of them.
+/// Values in `missing_values` (e.g. "NA") are missing, and rows with them are handled according to `missing`; rows that cannot be parsed are handled according to `invalid`.
+/// Warnings about skipped rows are only logged for the first `max_warnings` rows.
+/// Expressions can also use fields of each edge's nodes (e.g. "node1.pos" or "node2.maf"), from their position and `node_attrs`.
+/// Unless `has_header` or `column_names` (names of the columns of header-less input) are provided, whether the input has a header is detected (see `HeaderMode`).
+/// If several input files are read one after the other, they are recorded in `input_files` (while reading), to report edges by file.
+#[derive(Clone)]
+pub struct ReadOpts {
+    pub has_header: bool,
+    pub column_names: Option<Vec<String>>,
+    pub delimiter: Delimiter,
+    pub node_fields: Option<Vec<String>>,
+    pub pos_fields: Option<Vec<String>>,
+    pub matrix_sites: Option<PathBuf>,
+    pub weight_field: String,
+    pub weight_expr: Option<String>,
+    pub weight_filter: Option<String>,
+    pub weight_precision: u8,
+    pub sweep_filters: Vec<String>,
+    pub node_attrs: Option<NodeAttrs>,
+    pub missing_values: Vec<String>,
+    pub missing: MissingPolicy,
+    pub invalid: InvalidPolicy,
+    pub max_warnings: usize,
+    pub duplicates: DupPolicy,
+    pub self_loops: SelfLoopPolicy,
+    pub negative: NegativePolicy,
+    pub non_finite: NonFinitePolicy,
+    pub non_finite_cap: f64,
+    pub input_files: InputFiles,
+}
+
+impl ReadOpts {
+    /// Names of all columns referenced by the options (node, position, weight and filter fields).
+    pub fn columns(&self) -> BTreeSet<String> {
+        self.node_fields
+            .iter()
+            .chain(&self.pos_fields)
+            .flatten()
+            .flat_map(|field| field.split(':'))
+            .map(str::to_string)
+            .chain([self.weight_field.clone()])
+            .chain(
+                self.weight_expr
+                    .iter()
+                    .chain(&self.weight_filter)
+                    .chain(&self.sweep_filters)
+                    .filter_map(|expr| Expr::new(expr).ok())
+                    .flat_map(|expr| expr.vars()),
+            )
+            .collect()
+    }
+}
+
+impl Default for ReadOpts {
+    fn default() -> Self {
+        Self {
+            has_header: false,
+            column_names: None,
+            delimiter: Delimiter::default(),
+            node_fields: None,
+            pos_fields: None,
+            matrix_sites: None,
+            weight_field: "column_3".to_string(),
+            weight_expr: None,
+            weight_filter: None,
+            weight_precision: 4,
+            sweep_filters: Vec::new(),
+            node_attrs: None,
+            missing_values: vec!["NA".to_string(), "".to_string()],
+            missing: MissingPolicy::default(),
+            invalid: InvalidPolicy::default(),
+            max_warnings: 10,
+            duplicates: DupPolicy::default(),
+            self_loops: SelfLoopPolicy::default(),
+            negative: NegativePolicy::default(),
+            non_finite: NonFinitePolicy::default(),
+            non_finite_cap: 1.0,
+            input_files: InputFiles::default(),
+        }
+    }
+}
+
+/// Whether inputs have a header.
+#[derive(Clone, Debug, PartialEq)]
+pub enum HeaderMode {
+    /// Header given (e.g. with '--header')
+    Present,
+    /// No header, with column names given (e.g. with '--columns')
+    Absent,
+    /// Detect from the first line: it is a header if it has any of the `columns` referenced by the options, or a non-numeric (and non-missing) value in any of the `weight_columns` ("column_#")
+    Detect {
+        columns: BTreeSet<String>,
+        weight_columns: Vec<String>,
+        missing_values: Vec<String>,
+    },
+}
+
+/// Line of an input with its header.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum HeaderLine {
+    /// Last of the leading comments (e.g. "#CHROM\tPOS"), without the '#'
+    Comment,
+    /// First (non-comment) line
+    First,
+    /// Input has no header
+    None,
+}
+
+impl HeaderMode {
+    /// Resolve which line of an input is its header, from its last leading comment (without the '#', if any) and first line, along with the reason for it (to log).
+    ///
+    /// A leading comment can only be the header if it has as many fields as the first line.
+    pub fn resolve(
+        &self,
+        comment: Option<&str>,
+        line: &str,
+        delimiter: Delimiter,
+    ) -> (HeaderLine, String) {
+        let delimiter = match delimiter {
+            Delimiter::Auto => Delimiter::detect(line),
+            delimiter => delimiter,
+        };
+        let fields = delimiter.split(line);
+        let comment = comment
+            .map(|comment| delimiter.split(comment.trim_end()))
+            .filter(|comment| comment.len() == fields.len());
+        match (self, comment) {
+            (Self::Absent, _) => (
+                HeaderLine::None,
+                "none (column names given with '--columns')".to_string(),
+            ),
+            (Self::Present, Some(_)) => (
+                HeaderLine::Comment,
+                "given, on '#'-prefixed line".to_string(),
+            ),
+            (Self::Present, None) => (HeaderLine::First, "given".to_string()),
+            (Self::Detect { .. }, Some(comment)) if self.detect(&comment).is_some() => (
+                HeaderLine::Comment,
+                format!(
+                    "detected on '#'-prefixed line ({})",
+                    self.detect(&comment).unwrap()
+                ),
+            ),
+            (Self::Detect { .. }, _) => match self.detect(&fields) {
+                Some(reason) => (HeaderLine::First, format!("detected ({reason})")),
+                None => (
+                    HeaderLine::None,
+                    "none detected (first line has numeric weight values)".to_string(),
+                ),
+            },
+        }
+    }
+
+    /// Whether the fields of a line are a header (if detecting it), with the reason for it.
+    fn detect(&self, fields: &[String]) -> Option<String> {
+        let Self::Detect {
+            columns,
+            weight_columns,
+            missing_values,
+        } = self
+        else {
+            return None;
+        };
+        if let Some(field) = fields.iter().find(|field| columns.contains(*field)) {
+            return Some(format!("has field '{field}'"));
+        }
+        weight_columns.iter().find_map(|col| {
+            let i = col.strip_prefix("column_")?.parse::<usize>().ok()?;
+            let value = fields.get(i.checked_sub(1)?)?;
+            (value.trim().parse::<f64>().is_err() && !missing_values.contains(value))
+                .then(|| format!("non-numeric value '{value}' in weight column '{col}'"))
+        })
+    }
+}
+
+impl ReadOpts {
+    /// Whether inputs have a header (given, or detected from the columns referenced by the options).
+    pub fn header_mode(&self) -> HeaderMode {
+        if self.has_header {
+            HeaderMode::Present
+        } else if self.column_names.is_some() {
+            HeaderMode::Absent
+        } else {
+            HeaderMode::Detect {
+                columns: self
+                    .columns()
+                    .into_iter()
+                    .filter(|col| !col.starts_with("column_"))
+                    .collect(),
+                weight_columns: self
+                    .weight_expr
+                    .as_ref()
+                    .and_then(|expr| Expr::new(expr).ok())
+                    .map_or_else(
+                        || vec![self.weight_field.clone()],
+                        |expr| expr.vars().into_iter().collect(),
+                    ),
+                missing_values: self.missing_values.clone(),
+            }
+        }
+    }
+}
+
+/// Number of lines parsed (in parallel) per batch.
+const READ_BATCH: usize = 64 * 1024;
+
+/// Weight and filter expressions, parsed and compiled once.
+struct EdgeExprs {
+    weight: Option<Expr>,
+    filter: Option<Expr>,
+    sweeps: Vec<Expr>,
+}
+
+impl EdgeExprs {
+    fn new(opts: &ReadOpts) -> Self {
+        let compile = |kind: &str, expr: &String| {
+            Expr::new(expr).unwrap_or_else(|e| {
+                error!("cannot parse {kind} expression '{expr}': {e:?}");
+                std::process::exit(-1);
+            })
+        };
+        Self {
+            weight: opts.weight_expr.as_ref().map(|e| compile("weight", e)),
+            filter: opts.weight_filter.as_ref().map(|e| compile("filter", e)),
+            sweeps: opts
+                .sweep_filters
+                .iter()
+                .map(|e| compile("sweep", e))
+                .collect(),
+        }
+    }
+
+    /// Expressions with their kind and source (for error messages).
+    fn iter<'a>(
+        &'a self,
+        opts: &'a ReadOpts,
+    ) -> impl Iterator<Item = (&'static str, &'a String, &'a Expr)> {
+        self.weight
+            .iter()
+            .zip(&opts.weight_expr)
+            .map(|(expr, src)| ("weight", src, expr))
+            .chain(
+                self.filter
+                    .iter()
+                    .zip(&opts.weight_filter)
+                    .map(|(expr, src)| ("filter", src, expr)),
+            )
+            .chain(
+                self.sweeps
+                    .iter()
+                    .zip(&opts.sweep_filters)
+                    .map(|(expr, src)| ("sweep", src, expr)),
+            )
+    }
+}
+
+/// Input columns, resolved from the first line.
+struct Columns {
+    header: Vec<String>,
+    delimiter: Delimiter,
+    /// Node ID columns
+    nodes: Vec<Vec<usize>>,
+    /// Node position columns (chromosome and base-pair of each node)
+    pos: Vec<Vec<usize>>,
+    /// Columns used by the weight or filters (in the same order as `used`)
+    data: Vec<usize>,
+    /// Names of columns used by the weight or filters (sorted, so that each has a slot in the parsed row)
+    used: Vec<String>,
+    /// Whether each used column must be numeric (used by the weight or other than in '==' and '!=' comparisons), or can also have strings
+    numeric: Vec<bool>,
+}
+
+impl Columns {
+    fn new(line: &str, has_header: bool, opts: &ReadOpts, exprs: &EdgeExprs) -> Self {
+        // Detect delimiter
+        let delimiter = if opts.delimiter == Delimiter::Auto {
+            let delimiter = Delimiter::detect(line);
+            debug!("Using {delimiter} as delimiter");
+            delimiter
+        } else {
+            opts.delimiter
+        };
+
+        // Define header
+        let edge = delimiter.split(line);
+        let header: Vec<String> = match &opts.column_names {
+            _ if has_header => edge,
+            Some(names) if names.len() != edge.len() => {
+                error!(
+                    "{0} column names provided, but input has {1} fields (using {2} as delimiter)",
+                    names.len(),
+                    edge.len(),
+                    delimiter
+                );
+                std::process::exit(-1);
+            }
+            Some(names) => names.clone(),
+            None => (1..edge.len() + 1)
+                .map(|h| format!("column_{}", h))
+                .collect(),
+        };
+        debug!("HEADER = {:?}", header);
+
+        // Node ID columns (all other columns are data)
+        let fields_cols = |fields: &Vec<String>| -> Vec<Vec<usize>> {
+            fields
+                .iter()
+                .map(|field| {
+                    field
+                        .split(':')
+                        .map(|col| {
+                            header.iter().position(|h| h == col).unwrap_or_else(|| {
+                                error!("node field '{col}' is not present in the header");
+                                std::process::exit(-1);
+                            })
+                        })
+                        .collect()
+                })
+                .collect()
+        };
+        let nodes = opts
+            .node_fields
+            .as_ref()
+            .map_or_else(|| vec![vec![0], vec![1]], fields_cols);
+        if nodes.len() != 2 {
+            error!("two node fields are required, but {} provided", nodes.len());
+            std::process::exit(-1);
+        }
+
+        // Node position columns (chromosome and base-pair of each node)
+        let pos = opts.pos_fields.as_ref().map_or_else(Vec::new, fields_cols);
+        if opts.pos_fields.is_some() && (pos.len() != 2 || pos.iter().any(|cols| cols.len() != 2)) {
+            error!("position fields must be two pairs of chromosome and base-pair columns (e.g. 'CHR_A:BP_A,CHR_B:BP_B')");
+            std::process::exit(-1);
+        }
+        let data_cols: Vec<&str> = (0..header.len())
+            .filter(|i| !nodes.iter().flatten().any(|j| i == j))
+            .map(|i| header[i].as_str())
+            .collect();
+        debug!("Node columns: {:?}; data columns: {:?}", nodes, data_cols);
+
+        // Node fields (e.g. "node1.pos"), from their position and attributes
+        let node_fields: Vec<String> = ["node1", "node2"]
+            .iter()
+            .flat_map(|node| {
+                NODE_POS_FIELDS
+                    .iter()
+                    .map(|field| field.to_string())
+                    .chain(
+                        opts.node_attrs
+                            .iter()
+                            .flat_map(|attrs| attrs.header.clone()),
+                    )
+                    .map(move |field| format!("{node}.{field}"))
+            })
+            .collect();
+
+        // Check variables of all expressions (and weight field) before reading any data
+        // Expressions can also use node ID columns (e.g. "CHR_A == CHR_B")
+        let available: Vec<&str> = header
+            .iter()
+            .map(String::as_str)
+            .chain(node_fields.iter().map(String::as_str))
+            .collect();
+        for (kind, src, expr) in exprs.iter(opts) {
+            if let Err(e) = expr.check(&available) {
+                error!("{kind} expression '{src}': {e}");
+                std::process::exit(-1);
+            }
+        }
+        let weight_field = &opts.weight_field;
+        if exprs.weight.is_none() && !data_cols.contains(&weight_field.as_str()) {
+            match crate::expr::suggest(weight_field, &data_cols) {
+                Some(suggestion) => error!("weight field '{weight_field}' is not present in the header (did you mean '{suggestion}'?)"),
+                None if has_header && weight_field.starts_with("column_") => error!("weight field '{weight_field}' is not present in the header (input has a header, so use its column names)"),
+                None => error!("weight field '{weight_field}' is not present in the header"),
+            }
+            std::process::exit(-1);
+        }
+
+        // Columns used by the weight or filters (only these need to be parsed)
+        let weight_cols = exprs
+            .weight
+            .as_ref()
+            .map_or_else(|| BTreeSet::from([weight_field.clone()]), |e| e.vars());
+        let used: Vec<String> = weight_cols
+            .iter()
+            .cloned()
+            .chain(
+                exprs
+                    .filter
+                    .iter()
+                    .chain(&exprs.sweeps)
+                    .flat_map(|e| e.vars()),
+            )
+            .filter(|col| header.contains(col))
+            .collect::<BTreeSet<String>>()
+            .into_iter()
+            .collect();
+        debug!("Columns used: {:?}", used);
+        let numeric_cols: BTreeSet<String> = exprs
+            .filter
+            .iter()
+            .chain(&exprs.sweeps)
+            .flat_map(|e| e.vars_numeric())
+            .chain(weight_cols)
+            .collect();
+        let numeric = used.iter().map(|col| numeric_cols.contains(col)).collect();
+        let data: Vec<usize> = used
+            .iter()
+            .map(|col| header.iter().position(|h| h == col).unwrap())
+            .collect();
+
+        Self {
+            header,
+            delimiter,
+            nodes,
+            pos,
+            data,
+            used,
+            numeric,
+        }
+    }
+
+    /// Slot of a used column in the parsed row.
+    fn slot(&self, col: &str) -> Option<usize> {
+        self.used.binary_search_by(|c| c.as_str().cmp(col)).ok()
+    }
+}
+
+/// Node fields from their position (node ID or position fields), besides node attributes.
+const NODE_POS_FIELDS: [&str; 2] = ["chr", "pos"];
+
+/// Value of a field of one of an edge's nodes (e.g. "node1.maf").
+///
+/// Node attributes take precedence; "chr" and "pos" are taken from the position fields or parsed from node IDs ("chr:pos"). Missing values are NaN.
+fn node_field(
+    name: &str,
+    nodes: &[String],
+    pos: &[(String, String)],
+    attrs: Option<&NodeAttrs>,
+) -> Option<f64> {
+    let (node, field) = name.split_once('.')?;
+    let i = match node {
+        "node1" => 0,
+        "node2" => 1,
+        _ => return None,
+    };
+    if let Some(j) = attrs.and_then(|attrs| attrs.header.iter().position(|h| h == field)) {
+        return Some(
+            attrs
+                .and_then(|attrs| attrs.values.get(&nodes[i]))
+                .map_or(f64::NAN, |values| values[j]),
+        );
+    }
+    let (chr, bp) = match pos.get(i) {
+        Some((chr, bp)) => (chr.as_str(), bp.as_str()),
+        None => nodes[i].rsplit_once(':').unwrap_or(("", "")),
+    };
+    match field {
+        "chr" if !chr.is_empty() => Some(crate::expr::str_value(chr)),
+        "pos" => Some(bp.parse().unwrap_or(f64::NAN)),
+        "chr" => Some(f64::NAN),
+        _ => None,
+    }
+}
+
+/// Edge parsed from an input line, before being added to the graph.
+struct ParsedEdge {
+    /// Node IDs
+    nodes: Vec<String>,
+    /// Node positions (chromosome and base-pair fields)
+    pos: Vec<(String, String)>,
+    /// Edge weight and sweep bitmask (`None` if the edge is skipped or filtered out)
+    edge: Option<(f32, u64)>,
+    /// Parsed data row (only kept if filters are evaluated after merging duplicates)
+    row: Vec<f64>,
+    /// Reason (and description) if the row is skipped
+    skip: Option<(SkipReason, String)>,
+    non_finite: bool,
+    negative: bool,
+    self_loop: bool,
+}
+
+impl ParsedEdge {
+    /// Row skipped before its nodes are parsed.
+    fn skipped(reason: SkipReason, msg: String) -> Self {
+        Self {
+            nodes: Vec::new(),
+            pos: Vec::new(),
+            edge: None,
+            row: Vec::new(),
+            skip: Some((reason, msg)),
+            non_finite: false,
+            negative: false,
+            self_loop: false,
+        }
+    }
+}
+
+/// Parse an input line (at `loc`) into an edge.
+///
+/// Returns an error message if the edge is invalid (according to the read options).
+fn edge_parse(
+    line: &str,
+    loc: LineLoc,
+    cols: &Columns,
+    opts: &ReadOpts,
+    exprs: &EdgeExprs,
+) -> Result<ParsedEdge, String> {
+    let edge: Vec<String> = cols.delimiter.split(line);
+
+    // Check number of fields
+    if edge.len() != cols.header.len() {
+        let msg = format!(
+            "{0} has {1} fields, while header has {2} (using {3} as delimiter)",
+            loc,
+            edge.len(),
+            cols.header.len(),
+            cols.delimiter
+        );
+        return match opts.invalid {
+            InvalidPolicy::Error => Err(format!("{msg}; see option '--invalid'")),
+            InvalidPolicy::Skip => Ok(ParsedEdge::skipped(SkipReason::Invalid, msg)),
+        };
+    }
+
+    // Node IDs
+    let mut parsed = ParsedEdge {
+        nodes: cols
+            .nodes
+            .iter()
+            .map(|node_cols| {
+                node_cols
+                    .iter()
+                    .map(|i| edge[*i].as_str())
+                    .collect::<Vec<&str>>()
+                    .join(":")
+            })
+            .collect(),
+        pos: cols
+            .pos
+            .iter()
+            .map(|pos_cols| (edge[pos_cols[0]].clone(), edge[pos_cols[1]].clone()))
+            .collect(),
+        edge: None,
+        row: Vec::new(),
+        skip: None,
+        non_finite: false,
+        negative: false,
+        self_loop: false,
+    };
+
+    // Parse used columns into a row (one slot per column)
+    // Columns only compared for (in)equality take the value of their string (as string literals do), so they can be non-numeric
+    let mut row = Vec::with_capacity(cols.data.len());
+    for ((i, numeric), col) in cols.data.iter().zip(&cols.numeric).zip(&cols.used) {
+        let x = &edge[*i];
+        if opts.missing_values.contains(x) {
+            let msg = format!("{loc} has a missing value ('{x}') in column '{col}'");
+            match opts.missing {
+                MissingPolicy::Skip => {
+                    parsed.skip = Some((SkipReason::Missing, msg));
+                    return Ok(parsed);
+                }
+                MissingPolicy::Zero => row.push(0.0),
+                MissingPolicy::Error => return Err(format!("{msg}; see option '--missing'")),
+            }
+            continue;
+        }
+        if !numeric {
+            row.push(crate::expr::str_value(x));
+            continue;
+        }
+        row.push(match x.parse::<f32>() {
+            Ok(value) => round(value, opts.weight_precision.into()) as f64,
+            Err(_) => {
+                let msg = format!("{loc} has a non-numeric value ('{x}') in column '{col}'");
+                match opts.invalid {
+                    InvalidPolicy::Error => {
+                        return Err(format!(
+                            "{msg}; see options '--missing-values' and '--invalid'"
+                        ))
+                    }
+                    InvalidPolicy::Skip => {
+                        parsed.skip = Some((SkipReason::Invalid, msg));
+                        return Ok(parsed);
+                    }
+                }
+            }
+        });
+    }
+    if loc.line <= 20 {
+        debug!("Edge: {:?}", edge);
+        debug!(
+            "Edge weight: {:?}",
+            cols.used.iter().zip(&row).collect::<Vec<_>>()
+        );
+    }
+
+    // Handle non-finite values
+    if opts.non_finite != NonFinitePolicy::Keep && row.iter().any(|value| !value.is_finite()) {
+        parsed.non_finite = true;
+        match opts.non_finite {
+            NonFinitePolicy::Error => {
+                return Err(format!(
+                    "{loc} has non-finite values: {:?}; see option '--non-finite'",
+                    edge
+                ));
+            }
+            NonFinitePolicy::Cap if !row.iter().any(|value| value.is_nan()) => {
+                for value in row.iter_mut() {
+                    if value.is_infinite() {
+                        *value = opts.non_finite_cap.copysign(*value);
+                    }
+                }
+            }
+            _ => {
+                parsed.skip = Some((SkipReason::NonFinite, String::new()));
+                return Ok(parsed);
+            }
+        }
+    }
+
+    // Calculate edge weight
+    let mut ns = |name: &str, _args: Vec<f64>| {
+        cols.slot(name)
+            .map(|slot| row[slot])
+            .or_else(|| node_field(name, &parsed.nodes, &parsed.pos, opts.node_attrs.as_ref()))
+    };
+    let edge_weight = match &exprs.weight {
+        Some(weight_expr) => round(
+            weight_expr
+                .eval(&mut ns)
+                .expect("cannot evaluate weight expression") as f32,
+            opts.weight_precision.into(),
+        ),
+        None => row[cols.slot(&opts.weight_field).unwrap()] as f32,
+    };
+
+    // Weight expression can also produce non-finite values
+    let edge_weight = match weight_policy(edge_weight, opts) {
+        Ok(Ok(weight)) => {
+            parsed.non_finite |=
+                opts.non_finite != NonFinitePolicy::Keep && !edge_weight.is_finite();
+            parsed.negative = edge_weight < 0.0;
+            weight
+        }
+        Ok(Err(reason)) => {
+            parsed.non_finite |= reason == SkipReason::NonFinite;
+            let msg = if reason == SkipReason::Nan {
+                format!("{loc} has NaN weight: {edge:?}")
+            } else {
+                String::new()
+            };
+            parsed.skip = Some((reason, msg));
+            return Ok(parsed);
+        }
+        Err(e) => return Err(format!("{loc} {e}")),
+    };
+
+    // Check for self-loops
+    if parsed.nodes[0] == parsed.nodes[1] {
+        parsed.self_loop = true;
+        match opts.self_loops {
+            SelfLoopPolicy::Error => {
+                return Err(format!(
+                    "{loc} is a self-loop ({0}); see option '--self-loops'",
+                    parsed.nodes[0]
+                ));
+            }
+            SelfLoopPolicy::Skip => {
+                parsed.skip = Some((SkipReason::SelfLoop, String::new()));
+                return Ok(parsed);
+            }
+            SelfLoopPolicy::Keep => {}
+        }
+    }
+
+    // Filter edge (unless duplicates are merged first)
+    if opts.duplicates == DupPolicy::Keep {
+        parsed.edge = edge_filter(&parsed, &row, cols, opts, exprs)
+            .map(|edge_sweep| (edge_weight, edge_sweep));
+    } else {
+        parsed.edge = Some((edge_weight, 0));
+        parsed.row = row;
+    }
+
+    Ok(parsed)
+}
+
+/// Apply the non-finite and negative weight policies to an edge weight.
+///
+/// Returns the (possibly capped or changed) weight, the reason if the edge is skipped, or an error message (without the edge).
+fn weight_policy(mut weight: f32, opts: &ReadOpts) -> Result<Result<f32, SkipReason>, String> {
+    if opts.non_finite != NonFinitePolicy::Keep && !weight.is_finite() {
+        match opts.non_finite {
+            NonFinitePolicy::Error => {
+                return Err(format!(
+                    "has non-finite weight ({weight}); see option '--non-finite'"
+                ));
+            }
+            NonFinitePolicy::Cap if weight.is_infinite() => {
+                weight = (opts.non_finite_cap as f32).copysign(weight);
+            }
+            _ => return Ok(Err(SkipReason::NonFinite)),
+        }
+    }
+
+    // Skip edge if NaN
+    if weight.is_nan() {
+        return Ok(Err(SkipReason::Nan));
+    }
+
+    // Handle negative weights
+    if weight < 0.0 {
+        match opts.negative {
+            NegativePolicy::Keep => {}
+            NegativePolicy::Abs => weight = weight.abs(),
+            NegativePolicy::Zero => weight = 0.0,
+            NegativePolicy::Error => {
+                return Err(format!(
+                    "has negative weight ({weight}); see option '--negative'"
+                ));
+            }
+        }
+    }
+
+    Ok(Ok(weight))
+}
+
+/// Evaluate the filter and sweep filters on a parsed edge (with data `row`).
+///
+/// Returns the bitmask of the sweep filters the edge passes, or `None` if it is filtered out.
+fn edge_filter(
+    parsed: &ParsedEdge,
+    row: &[f64],
+    cols: &Columns,
+    opts: &ReadOpts,
+    exprs: &EdgeExprs,
+) -> Option<u64> {
+    let mut ns = |name: &str, _args: Vec<f64>| {
+        cols.slot(name)
+            .map(|slot| row[slot])
+            .or_else(|| node_field(name, &parsed.nodes, &parsed.pos, opts.node_attrs.as_ref()))
+    };
+
+    // Evaluate sweep filters
+    let edge_sweep = exprs
+        .sweeps
+        .iter()
+        .enumerate()
+        .filter(|(_, f)| f.eval(&mut ns).expect("cannot evaluate sweep expression") != 0.0)
+        .fold(0u64, |mask, (i, _)| mask | 1 << i);
+
+    // Filter edge
+    (exprs
+        .filter
+        .as_ref()
+        .is_none_or(|f| f.eval(&mut ns).expect("cannot evaluate filter expression") != 0.0)
+        && (exprs.sweeps.is_empty() || edge_sweep != 0))
+        .then_some(edge_sweep)
+}
+
+/// Read edges into a graph.
+///
+/// Lines are parsed in parallel (in batches), but nodes and edges are added to the graph in input order.
+/// If sweep filters are provided, a bitmask (per edge index) of the filters each edge passes is also returned.
+/// Input metadata (header and, if position fields are provided, the position of each node) is also returned.
 pub fn graph_read<R: BufRead>(
     reader: R,
-    has_header: bool,
-    weight_field: String,
-    weight_filter: Option<String>,
-    weight_n_edges: bool,
-    weight_precision: u8,
+    opts: &ReadOpts,
 ) -> (
-    StableGraph<String, f32, Undirected, GraphIdx>,
+    Graph,
     HashMap<String, NodeIndex<GraphIdx>>,
+    Vec<u64>,
+    GraphMeta,
 ) {
     // Create graph
-    let mut graph = StableGraph::<String, f32, Undirected, GraphIdx>::default();
+    let mut graph = Graph::default();
     debug!(
         "Creating graph with GraphIdx = {}",
         std::any::type_name::<GraphIdx>()
     );
     let mut graph_idx = HashMap::new();
+    let mut edges_sweep = Vec::<u64>::new();
+    let mut nodes_pos = HashMap::new();
+    // Parsed edges (by edge index), to filter after merging duplicates
+    let mut edges_parsed = Vec::<ParsedEdge>::new();
+
+    // Compile weight and filter expressions
+    let exprs = EdgeExprs::new(opts);
 
     // Initialize span and progress bar
     let graph_span = info_span!("graph");
@@ -48,194 +1182,821 @@ pub fn graph_read<R: BufRead>(
     );
     let graph_span_enter = graph_span.enter();
 
-    // Read the file line by line
-    let mut header: Vec<String> = Vec::new();
+    // Read the file line by line (skipping empty lines), keeping line numbers
+    let mut lines = reader
+        .lines()
+        .map(|line| {
+            line.unwrap_or_else(|e| {
+                error!("cannot read line from input file: {e}");
+                std::process::exit(-1);
+            })
+        })
+        .enumerate()
+        .map(|(i, line)| (i + 1, line))
+        .filter(|(_, line)| !line.trim().is_empty())
+        .peekable();
+
+    // Leading comments (the last of which can be a '#'-prefixed header), then skip all other comments
+    let mut comment = None;
+    while let Some((_, line)) = lines.next_if(|(_, line)| line.starts_with('#')) {
+        comment = line
+            .strip_prefix('#')
+            .filter(|line| !line.starts_with('#'))
+            .map(str::to_string);
+    }
+    let mut lines = lines.filter(|(_, line)| !line.starts_with('#')).peekable();
+
+    // Define columns from the header (or first line)
+    let Some((_, line)) = lines.peek() else {
+        return (graph, graph_idx, edges_sweep, GraphMeta::default());
+    };
+    let (header_line, reason) =
+        opts.header_mode()
+            .resolve(comment.as_deref(), line, opts.delimiter);
+    info!("Input header: {reason}");
+    let cols = match header_line {
+        HeaderLine::Comment => Columns::new(comment.as_deref().unwrap(), true, opts, &exprs),
+        HeaderLine::First => Columns::new(line, true, opts, &exprs),
+        HeaderLine::None => Columns::new(line, false, opts, &exprs),
+    };
+    if header_line == HeaderLine::First {
+        lines.next();
+    }
+
     let mut n_lines: usize = 0;
-    for (index, line) in reader.lines().enumerate() {
-        let line = line.expect("cannot read line from input file");
+    let mut n_self_loops: usize = 0;
+    let mut n_negative: usize = 0;
+    let mut n_non_finite: usize = 0;
+    let mut n_skipped = BTreeMap::<SkipReason, usize>::new();
+    let mut n_warnings: usize = 0;
+    // Number of lines and edges read from each input file (if several)
+    let mut n_lines_file = Vec::<(usize, usize)>::new();
+    loop {
+        let batch: Vec<(usize, String)> = lines.by_ref().take(READ_BATCH).collect();
+        if batch.is_empty() {
+            break;
+        }
+        let input_starts = opts.input_files.starts();
+        n_lines_file.resize(input_starts.len(), (0, 0));
+        let locs: Vec<LineLoc> = batch
+            .iter()
+            .map(|(n_line, _)| LineLoc::new(&input_starts, *n_line))
+            .collect();
+
+        // Parse lines in parallel (keeping their order)
+        let parsed: Vec<Result<ParsedEdge, String>> = batch
+            .par_iter()
+            .zip(locs.par_iter())
+            .map(|((_, line), loc)| edge_parse(line, *loc, &cols, opts, &exprs))
+            .collect();
+
+        for (loc, parsed) in locs.iter().zip(parsed) {
+            n_lines += 1;
+            let parsed = parsed.unwrap_or_else(|e| {
+                error!("{e}");
+                std::process::exit(-1);
+            });
+
+            // Check if nodes exist and add them if not
+            // Node label is stored as its "weight"
+            for (i, node) in parsed.nodes.iter().enumerate() {
+                if !graph_idx.contains_key(node) {
+                    graph_idx.insert(node.clone(), graph.add_node(node.clone()));
+                    if let Some((chr, bp)) = parsed.pos.get(i) {
+                        nodes_pos.insert(
+                            node.clone(),
+                            NodePos {
+                                chr: chr.clone(),
+                                bp: bp.parse().unwrap_or_else(|_| {
+                                    error!(
+                                        "cannot convert position '{bp}' of node '{node}' to integer"
+                                    );
+                                    std::process::exit(-1);
+                                }),
+                            },
+                        );
+                    }
+                }
+            }
+            trace!("Graph: {:?}", graph);
+
+            n_non_finite += parsed.non_finite as usize;
+            n_negative += parsed.negative as usize;
+            n_self_loops += parsed.self_loop as usize;
+            if let Some((reason, msg)) = &parsed.skip {
+                *n_skipped.entry(*reason).or_default() += 1;
+                if !msg.is_empty() {
+                    n_warnings += 1;
+                    if n_warnings <= opts.max_warnings {
+                        warn!("Skipping row: {msg}");
+                    }
+                    if n_warnings == opts.max_warnings + 1 {
+                        warn!("Too many skipped rows; further warnings suppressed (see option '--max-warnings')");
+                    }
+                }
+            }
+
+            if let Some((i, _)) = loc.file {
+                n_lines_file[i].0 += 1;
+                n_lines_file[i].1 += parsed.edge.is_some() as usize;
+            }
+
+            // Add edge to graph
+            if let Some((edge_weight, edge_sweep)) = parsed.edge {
+                let e1 = graph.add_edge(
+                    graph_idx[&parsed.nodes[0]],
+                    graph_idx[&parsed.nodes[1]],
+                    edge_weight,
+                );
+                // Edge indices are contiguous, since no edges are removed while reading
+                if !opts.sweep_filters.is_empty() {
+                    edges_sweep.push(edge_sweep);
+                }
+                // Debug
+                if n_lines <= 20 {
+                    debug!("Added edge: {:?}", e1);
+                }
+                if opts.duplicates != DupPolicy::Keep {
+                    edges_parsed.push(parsed);
+                }
+            }
+        }
+
         // Update progress bar
-        graph_span.pb_inc(1);
+        graph_span.pb_inc(batch.len() as u64);
         if enabled!(Level::DEBUG) {
-            graph_span.pb_set_message(&*format!(
+            graph_span.pb_set_message(&format!(
                 "for graph with {0} nodes and {1} edges",
                 graph.node_count(),
                 graph.edge_count()
             ));
         }
+    }
+    std::mem::drop(graph_span_enter);
+    std::mem::drop(graph_span);
+    let input_starts = opts.input_files.starts();
+    n_lines_file.resize(input_starts.len(), (0, 0));
+    for ((_, path), (n_lines, n_edges)) in input_starts.iter().zip(n_lines_file) {
+        info!("Read {n_edges} edge(s) from {n_lines} line(s) of {path:?}");
+    }
+    info!("Read {n_lines} line(s) from input");
 
-        //let edge: Vec<&str> = line.split('\t').collect();
-        let edge: Vec<String> = line.split('\t').map(str::to_string).collect();
-
-        // Define header
-        if index == 0 {
-            header = if has_header {
-                edge.clone()
+    if !n_skipped.is_empty() {
+        warn!(
+            "Skipped {0} row(s): {1}",
+            n_skipped.values().sum::<usize>(),
+            n_skipped
+                .iter()
+                .map(|(reason, n)| format!("{n} {reason}"))
+                .collect::<Vec<String>>()
+                .join(", ")
+        );
+    }
+    if n_non_finite > 0 {
+        warn!(
+            "{n_non_finite} edge(s) with non-finite values {0}",
+            if opts.non_finite == NonFinitePolicy::Cap {
+                "capped (or skipped, if NaN)"
             } else {
-                (1..edge.len() + 1)
-                    .map(|h| format!("column_{}", h))
-                    .collect()
-            };
-            debug!("HEADER = {:?}", header);
-            if !header.iter().any(|h| h == &weight_field) {
-                error!("weight_field '{weight_field}' is not present in the header");
-                std::process::exit(-1);
+                "skipped"
             }
-            if has_header {
-                continue;
+        );
+    }
+    if n_negative > 0 {
+        debug!(
+            "{n_negative} edge(s) with negative weight ({0:?})",
+            opts.negative
+        );
+    }
+
+    // Merge duplicated edges and then filter them
+    let mut n_dups = 0;
+    if opts.duplicates != DupPolicy::Keep {
+        let edges_merged;
+        let mut n_merged_skipped = 0;
+        (n_dups, edges_merged) = graph_dedup(&mut graph, opts.duplicates, opts.weight_precision);
+        // Numeric columns are merged like the weight (and the weight expression is evaluated on them), so that filters apply to the merged values
+        for (edge, group) in edges_merged {
+            let mut row: Vec<f64> = (0..cols.used.len())
+                .map(|slot| {
+                    let values = group.iter().map(|e| edges_parsed[e.index()].row[slot]);
+                    if !cols.numeric[slot] {
+                        return edges_parsed[edge.index()].row[slot];
+                    }
+                    let value = match opts.duplicates {
+                        DupPolicy::Mean => values.sum::<f64>() / group.len() as f64,
+                        _ => values.sum::<f64>(),
+                    };
+                    round(value as f32, opts.weight_precision.into()) as f64
+                })
+                .collect();
+            let parsed = &edges_parsed[edge.index()];
+            match &exprs.weight {
+                Some(weight_expr) => {
+                    let mut ns = |name: &str, _args: Vec<f64>| {
+                        cols.slot(name).map(|slot| row[slot]).or_else(|| {
+                            node_field(name, &parsed.nodes, &parsed.pos, opts.node_attrs.as_ref())
+                        })
+                    };
+                    let weight = round(
+                        weight_expr
+                            .eval(&mut ns)
+                            .expect("cannot evaluate weight expression")
+                            as f32,
+                        opts.weight_precision.into(),
+                    );
+                    match weight_policy(weight, opts) {
+                        Ok(Ok(weight)) => graph[edge] = weight,
+                        Ok(Err(reason)) => {
+                            n_merged_skipped += 1;
+                            debug!(
+                                "Skipping merged edge {0} - {1} ({reason})",
+                                parsed.nodes[0], parsed.nodes[1]
+                            );
+                            graph.remove_edge(edge);
+                        }
+                        Err(e) => {
+                            error!(
+                                "merged edge {0} - {1} {e}",
+                                parsed.nodes[0], parsed.nodes[1]
+                            );
+                            std::process::exit(-1);
+                        }
+                    }
+                }
+                None => row[cols.slot(&opts.weight_field).unwrap()] = graph[edge] as f64,
             }
+            edges_parsed[edge.index()].row = row;
         }
-        n_lines += 1;
-
-        // Check number of fields
-        if edge.len() != header.len() {
-            error!(
-                "edge {0} has {1} fields, while header has {2}",
-                n_lines,
-                edge.len(),
-                header.len()
-            );
-            std::process::exit(-1);
+        if n_merged_skipped > 0 {
+            warn!("Skipped {n_merged_skipped} merged edge(s) with non-finite or NaN weight");
         }
 
-        // Check if nodes exist and add them if not
-        // Node label is stored as its "weight"
-        if !graph_idx.contains_key(&edge[0]) {
-            graph_idx.insert(edge[0].clone(), graph.add_node(edge[0].clone()));
+        let edges: Vec<EdgeIndex<GraphIdx>> = graph.edge_indices().collect();
+        let edges_mask: Vec<Option<u64>> = edges
+            .par_iter()
+            .map(|edge| {
+                let parsed = &edges_parsed[edge.index()];
+                edge_filter(parsed, &parsed.row, &cols, opts, &exprs)
+            })
+            .collect();
+        for (edge, edge_mask) in edges.into_iter().zip(edges_mask) {
+            match edge_mask {
+                Some(edge_sweep) if !edges_sweep.is_empty() => {
+                    edges_sweep[edge.index()] = edge_sweep
+                }
+                Some(_) => {}
+                None => {
+                    graph.remove_edge(edge);
+                }
+            }
         }
-        if !graph_idx.contains_key(&edge[1]) {
-            graph_idx.insert(edge[1].clone(), graph.add_node(edge[1].clone()));
+    }
+
+    debug!(
+        "Input file has {0} nodes with {1} edges{2} [{3} duplicate(s) merged, {4} self-loop(s) {5}]",
+        graph.node_count(),
+        n_lines,
+        if let Some(weight_filter) = &opts.weight_filter {
+            format!(" ({0} edges with {1})", graph.edge_count(), weight_filter)
+        } else {
+            "".to_string()
+        },
+        n_dups,
+        n_self_loops,
+        if opts.self_loops == SelfLoopPolicy::Keep {
+            "kept"
+        } else {
+            "skipped"
         }
-        trace!("Graph: {:?}", graph);
+    );
 
-        // Prepare dict for ez_eval
-        use std::collections::BTreeMap;
-        let mut edge_weights: BTreeMap<String, f64> = BTreeMap::from_iter(
-            edge.iter()
-                .skip(2)
-                .map(|x| {
+    if !nodes_pos.is_empty() {
+        debug!("Kept position of {} node(s)", nodes_pos.len());
+    }
+
+    let meta = GraphMeta {
+        header: cols.header,
+        nodes_pos,
+    };
+    (graph, graph_idx, edges_sweep, meta)
+}
+
+/// Edge merged from duplicates (including itself).
+type MergedEdge = (EdgeIndex<GraphIdx>, Vec<EdgeIndex<GraphIdx>>);
+
+/// Merge edges between the same pair of nodes (in any direction), according to `policy`.
+///
+/// Since edge indices follow the input order, the first occurrence is the one with the lowest index. Returns the number of edges merged, and the edges whose weight was aggregated (mean or sum), with the duplicates they were merged from (including themselves).
+fn graph_dedup(
+    graph: &mut Graph,
+    policy: DupPolicy,
+    weight_precision: u8,
+) -> (usize, Vec<MergedEdge>) {
+    let mut n_dups = 0;
+    let mut edges_merged = Vec::new();
+    for node in graph.node_indices().collect::<Vec<NodeIndex<GraphIdx>>>() {
+        // Edges to nodes with higher (or the same) index, so each pair is only processed once
+        let mut edges: Vec<(NodeIndex<GraphIdx>, EdgeIndex<GraphIdx>)> = graph
+            .edges(node)
+            .map(|edge| {
+                let node_other = if edge.source() == node {
+                    edge.target()
+                } else {
+                    edge.source()
+                };
+                (node_other, edge.id())
+            })
+            .filter(|(node_other, _)| *node_other >= node)
+            .collect();
+        // Self-loops can be listed twice (as outgoing and incoming)
+        edges.sort_unstable();
+        edges.dedup();
+
+        for dups in edges
+            .chunk_by(|a, b| a.0 == b.0)
+            .filter(|dups| dups.len() > 1)
+        {
+            if policy == DupPolicy::Error {
+                error!(
+                    "edge {0} - {1} is present {2} times in the input; see option '--duplicates'",
+                    graph[node],
+                    graph[dups[0].0],
+                    dups.len()
+                );
+                std::process::exit(-1);
+            }
+
+            let weights: Vec<f32> = dups.iter().map(|(_, edge)| graph[*edge]).collect();
+            let (edge_keep, weight) = match policy {
+                DupPolicy::First => (dups[0].1, weights[0]),
+                DupPolicy::Last => (dups[dups.len() - 1].1, weights[dups.len() - 1]),
+                DupPolicy::Max | DupPolicy::Min => {
+                    let i = (0..dups.len())
+                        .reduce(|i, j| {
+                            if (policy == DupPolicy::Max && weights[j] > weights[i])
+                                || (policy == DupPolicy::Min && weights[j] < weights[i])
+                            {
+                                j
+                            } else {
+                                i
+                            }
+                        })
+                        .unwrap();
+                    (dups[i].1, weights[i])
+                }
+                DupPolicy::Mean => (
+                    dups[0].1,
                     round(
-                        x.parse::<f32>()
-                            .unwrap_or_else(|_| panic!("cannot convert weight '{x}' to float32")),
+                        weights.iter().sum::<f32>() / weights.len() as f32,
                         weight_precision.into(),
-                    ) as f64
-                })
-                .enumerate()
-                .map(|(i, w)| (header[i + 2].clone(), w)),
-        );
+                    ),
+                ),
+                DupPolicy::Sum => (
+                    dups[0].1,
+                    round(weights.iter().sum::<f32>(), weight_precision.into()),
+                ),
+                DupPolicy::Keep | DupPolicy::Error => unreachable!(),
+            };
 
-        // Debug
-        if index < 20 {
-            debug!("Edge: {:?}", edge);
-            debug!("Node1 weight: {:?}", graph.node_weight(graph_idx[&edge[0]]));
-            debug!("Node2 weight: {:?}", graph.node_weight(graph_idx[&edge[1]]));
-            debug!("Edge weight: {:?}", edge_weights);
+            if matches!(policy, DupPolicy::Mean | DupPolicy::Sum) {
+                edges_merged.push((edge_keep, dups.iter().map(|(_, edge)| *edge).collect()));
+            }
+            graph[edge_keep] = weight;
+            for (_, edge) in dups.iter().filter(|(_, edge)| *edge != edge_keep) {
+                graph.remove_edge(*edge);
+                n_dups += 1;
+            }
         }
+    }
 
-        // Skip edge if NaN
-        if edge_weights[&weight_field].is_nan() {
-            warn!("NaN found:\n\t{:?}", edge);
-            continue;
-        }
+    (n_dups, edges_merged)
+}
 
-        // Add edge to graph
-        if weight_filter.is_none()
-            || fasteval::ez_eval(weight_filter.as_ref().unwrap(), &mut edge_weights)
-                .expect("cannot evaluate expression")
-                != 0.0
-        {
-            // Add edge
-            let e1 = graph.add_edge(
-                graph_idx[&edge[0]],
-                graph_idx[&edge[1]],
-                if weight_n_edges {
-                    1.0
-                } else {
-                    edge_weights[&weight_field] as f32
-                },
+/// Combine several graphs (layers) with the same node IDs into a single one.
+///
+/// The weight of each edge is combined across layers according to `combine` (using `layer_sizes` for the weighted mean). Edges absent from a layer take the `missing` weight or, if not provided, are ignored for that layer.
+/// Each layer must have at most one edge between each pair of nodes (i.e. duplicates are merged when reading it, see `DupPolicy`).
+pub fn graph_layers(
+    layers: Vec<Graph>,
+    combine: LayerCombine,
+    layer_sizes: &[f64],
+    missing: Option<f32>,
+    weight_precision: u8,
+) -> Graph {
+    let mut graph = Graph::default();
+    let mut graph_idx = HashMap::<String, NodeIndex<GraphIdx>>::new();
+    // Weight of each edge (in order of first occurrence) on each layer
+    let mut edges_idx = HashMap::<(NodeIndex<GraphIdx>, NodeIndex<GraphIdx>), usize>::new();
+    let mut edges_nodes = Vec::<(NodeIndex<GraphIdx>, NodeIndex<GraphIdx>)>::new();
+    let mut edges_weights = Vec::<Vec<Option<f32>>>::new();
+
+    for (layer_idx, layer) in layers.iter().enumerate() {
+        for node in layer.node_indices() {
+            let label = &layer[node];
+            if !graph_idx.contains_key(label) {
+                graph_idx.insert(label.clone(), graph.add_node(label.clone()));
+            }
+        }
+        for edge in layer.edge_references() {
+            let (a, b) = (
+                graph_idx[&layer[edge.source()]],
+                graph_idx[&layer[edge.target()]],
             );
-            // Debug
-            if index < 20 {
-                debug!("Added edge: {:?}", e1);
+            let key = (a.min(b), a.max(b));
+            let i = *edges_idx.entry(key).or_insert_with(|| {
+                edges_nodes.push((a, b));
+                edges_weights.push(vec![None; layers.len()]);
+                edges_nodes.len() - 1
+            });
+            if edges_weights[i][layer_idx].is_some() {
+                error!(
+                    "edge {0} - {1} is present more than once in layer {2}; see option '--duplicates'",
+                    layer[edge.source()],
+                    layer[edge.target()],
+                    layer_idx + 1
+                );
+                std::process::exit(-1);
             }
+            edges_weights[i][layer_idx] = Some(*edge.weight());
         }
+        debug!(
+            "Layer {0} has {1} nodes with {2} edges",
+            layer_idx + 1,
+            layer.node_count(),
+            layer.edge_count()
+        );
     }
-    std::mem::drop(graph_span_enter);
-    std::mem::drop(graph_span);
 
-    debug!(
-        "Input file has {0} nodes with {1} edges{2}",
-        graph.node_count(),
-        n_lines,
-        if weight_filter.is_some() {
+    for ((a, b), weights) in edges_nodes.into_iter().zip(edges_weights) {
+        let weights: Vec<(f32, f64)> = weights
+            .iter()
+            .zip(layer_sizes.iter().copied().chain(std::iter::repeat(1.0)))
+            .filter_map(|(weight, size)| weight.or(missing).map(|weight| (weight, size)))
+            .collect();
+        let weight = match combine {
+            LayerCombine::Max => weights.iter().map(|(w, _)| *w).fold(f32::MIN, f32::max),
+            LayerCombine::Min => weights.iter().map(|(w, _)| *w).fold(f32::MAX, f32::min),
+            LayerCombine::Mean => {
+                weights.iter().map(|(w, _)| w).sum::<f32>() / weights.len() as f32
+            }
+            LayerCombine::WeightedMean => {
+                (weights.iter().map(|(w, n)| *w as f64 * n).sum::<f64>()
+                    / weights.iter().map(|(_, n)| n).sum::<f64>()) as f32
+            }
+        };
+        graph.add_edge(a, b, round(weight, weight_precision.into()));
+    }
+
+    graph
+}
+
+/// Check that the filter and sweep filters of `opts` only use the weight field (see `graph_filter`), so that they can be checked before reading the input.
+pub fn graph_filter_check(opts: &ReadOpts) -> Result<(), String> {
+    let exprs = EdgeExprs::new(opts);
+    let weight_field = opts.weight_field.as_str();
+    for (kind, src, expr) in exprs.iter(opts).filter(|(kind, _, _)| *kind != "weight") {
+        expr.check(&[weight_field]).map_err(|e| {
             format!(
-                " ({0} edges with {1})",
-                graph.edge_count(),
-                weight_filter.unwrap()
+                "{kind} expression '{src}' can only use the weight field ('{weight_field}'): {e}"
             )
+        })?;
+    }
+    Ok(())
+}
+
+/// Filter edges on their (e.g. combined across layers) weight, returning the bitmask (per edge index) of the sweep filters each edge passes.
+///
+/// The filter and sweep filters of `opts` can only use the weight field, which takes the edge weight.
+pub fn graph_filter(graph: &mut Graph, opts: &ReadOpts) -> Vec<u64> {
+    graph_filter_check(opts).unwrap_or_else(|e| {
+        error!("{e}");
+        std::process::exit(-1);
+    });
+    let exprs = EdgeExprs::new(opts);
+    let weight_field = opts.weight_field.as_str();
+
+    let mut edges_sweep = vec![0; graph.edge_bound()];
+    for edge in graph.edge_indices().collect::<Vec<EdgeIndex<GraphIdx>>>() {
+        let weight = graph[edge] as f64;
+        let mut ns = |name: &str, _args: Vec<f64>| (name == weight_field).then_some(weight);
+        let edge_sweep = exprs
+            .sweeps
+            .iter()
+            .enumerate()
+            .filter(|(_, f)| f.eval(&mut ns).expect("cannot evaluate sweep expression") != 0.0)
+            .fold(0u64, |mask, (i, _)| mask | 1 << i);
+        if exprs
+            .filter
+            .as_ref()
+            .is_none_or(|f| f.eval(&mut ns).expect("cannot evaluate filter expression") != 0.0)
+            && (exprs.sweeps.is_empty() || edge_sweep != 0)
+        {
+            edges_sweep[edge.index()] = edge_sweep;
         } else {
-            "".to_string()
+            graph.remove_edge(edge);
         }
-    );
+    }
+
+    if opts.sweep_filters.is_empty() {
+        Vec::new()
+    } else {
+        edges_sweep
+    }
+}
+
+/// Add nodes absent from the graph (as isolated nodes), returning how many were added.
+pub fn graph_add_nodes(graph: &mut Graph, nodes: Vec<String>) -> usize {
+    let nodes_graph: HashSet<String> = graph.node_weights().cloned().collect();
+    let mut n_added = 0;
+    for node in nodes {
+        if !nodes_graph.contains(&node) {
+            graph.add_node(node);
+            n_added += 1;
+        }
+    }
+    n_added
+}
+
+/// Read a list of nodes (e.g. all sites), one per line, with their positions (if available).
+///
+/// Node IDs are taken from the first field of each line, except for PLINK '.bim' files (second field, with chromosome and position on the first and fourth) and '.pos' files (chromosome and position on the first two fields, with "chr:pos" as ID); files can be compressed, and a '.pos' header is skipped.
+pub fn nodes_read(path: &Path) -> (Vec<String>, NodesPos) {
+    let file_name = path
+        .file_name()
+        .and_then(|f| f.to_str())
+        .unwrap_or_default()
+        .trim_end_matches(".gz");
+    let (is_bim, is_pos) = (file_name.ends_with(".bim"), file_name.ends_with(".pos"));
+    let mut nodes = Vec::new();
+    let mut nodes_pos = NodesPos::new();
+    for (index, line) in crate::input::input_open(Some(path)).lines().enumerate() {
+        let line = line.expect("cannot read line from nodes file");
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        if fields.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let (node, pos) = match fields[..] {
+            [chr, id, _, bp, ..] if is_bim => (id.to_string(), Some((chr, bp))),
+            [chr, bp, ..] if is_pos => (format!("{chr}:{bp}"), Some((chr, bp))),
+            [id, ..] if !is_bim && !is_pos => (id.to_string(), None),
+            _ => {
+                error!("invalid line in nodes file {:?}: {line}", path);
+                std::process::exit(-1);
+            }
+        };
+        if let Some((chr, bp)) = pos {
+            match bp.parse() {
+                Ok(bp) => {
+                    nodes_pos.insert(
+                        node.clone(),
+                        NodePos {
+                            chr: chr.to_string(),
+                            bp,
+                        },
+                    );
+                }
+                Err(_) if index == 0 => continue,
+                Err(_) => {
+                    error!("cannot convert position '{bp}' of node '{node}' to integer");
+                    std::process::exit(-1);
+                }
+            }
+        }
+        nodes.push(node);
+    }
+    debug!("Read {} node(s) from {:?}", nodes.len(), path);
+    (nodes, nodes_pos)
+}
+
+/// Nodes in the graph absent from `nodes`, and number of edges involving any of them.
+pub fn graph_nodes_unknown(graph: &Graph, nodes: &[String]) -> (Vec<String>, usize) {
+    let nodes: HashSet<&String> = nodes.iter().collect();
+    let unknown: HashSet<NodeIndex<GraphIdx>> = graph
+        .node_indices()
+        .filter(|node| !nodes.contains(&graph[*node]))
+        .collect();
+    let n_edges = graph
+        .edge_references()
+        .filter(|edge| unknown.contains(&edge.source()) || unknown.contains(&edge.target()))
+        .count();
+    let mut unknown: Vec<String> = unknown
+        .into_iter()
+        .map(|node| graph[node].clone())
+        .collect();
+    unknown.sort();
+    (unknown, n_edges)
+}
+
+pub fn graph_subset(graph: &mut Graph, subset: PathBuf) -> usize {
+    let mut nodes_subset = Vec::<String>::new();
+    let reader_file = BufReader::new(File::open(subset).expect("cannot open subset file"));
+    for node in reader_file.lines() {
+        nodes_subset.push(node.expect("cannot read node from subset file"));
+    }
+    debug!("Nodes to include: {:?}", nodes_subset);
+
+    graph.retain_nodes(|g, ix| nodes_subset.contains(&g[ix]));
+
+    nodes_subset.len()
+}
 
-    (graph, graph_idx)
+/// Numeric node attributes, keyed by node label.
+#[derive(Clone, Default)]
+pub struct NodeAttrs {
+    pub header: Vec<String>,
+    pub values: HashMap<String, Vec<f64>>,
+}
+
+/// Read node attributes from a TSV file with header (first column has the node IDs).
+pub fn node_attrs_read(path: PathBuf) -> NodeAttrs {
+    let reader_file = BufReader::new(File::open(path).expect("cannot open node attributes file"));
+    let mut node_attrs = NodeAttrs::default();
+    for (index, line) in reader_file.lines().enumerate() {
+        let line = line.expect("cannot read line from node attributes file");
+        let fields: Vec<&str> = line.split('\t').collect();
+        if index == 0 {
+            node_attrs.header = fields.iter().skip(1).map(|h| h.to_string()).collect();
+            debug!("Node attributes: {:?}", node_attrs.header);
+            continue;
+        }
+        if fields.len() != node_attrs.header.len() + 1 {
+            error!(
+                "node {0} has {1} attributes, while header has {2}",
+                fields[0],
+                fields.len() - 1,
+                node_attrs.header.len()
+            );
+            std::process::exit(-1);
+        }
+        node_attrs.values.insert(
+            fields[0].to_string(),
+            fields
+                .iter()
+                .skip(1)
+                .map(|x| {
+                    x.parse::<f64>()
+                        .unwrap_or_else(|_| panic!("cannot convert node attribute '{x}' to float"))
+                })
+                .collect(),
+        );
+    }
+
+    node_attrs
+}
+
+/// Aggregates of the weights of a node's edges, available to node score expressions.
+const NODE_SCORE_VARS: [&str; 5] = ["sum", "max", "mean", "count", "sumsq"];
+
+/// Node score, as an expression over aggregates of its edges' weights and (optionally) node attributes.
+pub struct NodeScore<'a> {
+    kind: NodeScoreKind,
+    attrs: Option<&'a NodeAttrs>,
+}
+
+/// Node scores computed directly ("sum" and "count") or through an expression.
+enum NodeScoreKind {
+    Sum,
+    Count,
+    Expr(Box<Expr>),
+}
+
+impl<'a> NodeScore<'a> {
+    pub fn new(expr: &str, attrs: Option<&'a NodeAttrs>) -> Result<Self, String> {
+        let kind = match expr.trim() {
+            "sum" => NodeScoreKind::Sum,
+            "count" => NodeScoreKind::Count,
+            _ => {
+                let expr = Expr::new(expr)
+                    .map_err(|e| format!("cannot parse node score expression '{expr}': {e:?}"))?;
+                for var in expr.vars() {
+                    if !NODE_SCORE_VARS.contains(&var.as_str())
+                        && !attrs.is_some_and(|attrs| attrs.header.contains(&var))
+                    {
+                        return Err(format!(
+                            "unknown variable '{var}' in node score expression (available: {0})",
+                            NODE_SCORE_VARS
+                                .iter()
+                                .map(|v| v.to_string())
+                                .chain(attrs.iter().flat_map(|attrs| attrs.header.clone()))
+                                .collect::<Vec<String>>()
+                                .join(", ")
+                        ));
+                    }
+                }
+                NodeScoreKind::Expr(Box::new(expr))
+            }
+        };
+        Ok(Self { kind, attrs })
+    }
+
+    /// Score of a node; node attributes missing for this node are NaN.
+    fn eval<'w>(&self, node_label: &str, weights: impl Iterator<Item = &'w f32>) -> f32 {
+        let expr = match &self.kind {
+            NodeScoreKind::Sum => return weights.sum(),
+            NodeScoreKind::Count => return weights.count() as f32,
+            NodeScoreKind::Expr(expr) => expr,
+        };
+
+        let (mut sum, mut max, mut count, mut sumsq) = (0f32, f32::NEG_INFINITY, 0usize, 0f32);
+        for weight in weights {
+            sum += weight;
+            max = max.max(*weight);
+            count += 1;
+            sumsq += weight * weight;
+        }
+        if count == 0 {
+            max = 0.0;
+        }
+
+        expr.eval(&mut |name: &str, _args: Vec<f64>| match name {
+            "sum" => Some(sum as f64),
+            "max" => Some(max as f64),
+            "mean" => Some(if count == 0 {
+                0.0
+            } else {
+                sum as f64 / count as f64
+            }),
+            "count" => Some(count as f64),
+            "sumsq" => Some(sumsq as f64),
+            _ => self.attrs.and_then(|attrs| {
+                let i = attrs.header.iter().position(|h| h == name)?;
+                Some(
+                    attrs
+                        .values
+                        .get(node_label)
+                        .map_or(f64::NAN, |values| values[i]),
+                )
+            }),
+        })
+        .expect("cannot evaluate node score expression") as f32
+    }
 }
 
-pub fn graph_subset(
-    graph: &mut StableGraph<String, f32, Undirected, GraphIdx>,
-    subset: PathBuf,
-) -> usize {
-    let mut nodes_subset = Vec::<String>::new();
-    let reader_file = BufReader::new(File::open(subset).expect("cannot open subset file"));
-    for node in reader_file.lines() {
-        nodes_subset.push(node.expect("cannot read node from subset file"));
+impl Default for NodeScore<'_> {
+    fn default() -> Self {
+        Self::new("sum", None).unwrap()
     }
-    debug!("Nodes to include: {:?}", nodes_subset);
-
-    graph.retain_nodes(|g, ix| nodes_subset.contains(&g[ix]));
-
-    nodes_subset.len()
 }
 
 fn get_node_weight(
     node_idx: NodeIndex<GraphIdx>,
-    g: &StableGraph<String, f32, Undirected, GraphIdx>,
+    g: &Graph,
+    node_score: &NodeScore,
 ) -> (NodeIndex<GraphIdx>, f32) {
     (
         node_idx,
-        g.edges(node_idx)
-            .map(|edge| -> &f32 { edge.weight() })
-            .sum::<f32>(),
+        node_score.eval(
+            &g[node_idx],
+            g.edges(node_idx).map(|edge| -> &f32 { edge.weight() }),
+        ),
     )
 }
 
 fn get_nodes_weight<I>(
     iter: I,
-    g: &StableGraph<String, f32, Undirected, GraphIdx>,
+    g: &Graph,
+    node_score: &NodeScore,
 ) -> Vec<(NodeIndex<GraphIdx>, f32)>
 where
     I: Iterator<Item = NodeIndex<GraphIdx>>,
 {
     iter.collect::<Vec<NodeIndex<GraphIdx>>>()
         .par_iter()
-        .map(|node_idx| get_node_weight(*node_idx, g))
+        .map(|node_idx| get_node_weight(*node_idx, g, node_score))
         .collect()
 }
 
+/// Find heaviest node, with ties broken alphabetically or (if `seed` is provided) randomly.
 pub fn find_heaviest_node(
-    g: &StableGraph<String, f32, Undirected, GraphIdx>,
+    g: &Graph,
     nodes_idx: Option<&Vec<NodeIndex<GraphIdx>>>,
+    seed: Option<u64>,
+    node_score: &NodeScore,
 ) -> (NodeIndex<GraphIdx>, f32) {
     // Calculate each node's weight
     let mut nodes_weight = nodes_idx.map_or_else(
-        || get_nodes_weight(g.node_indices(), g),
-        |vec| get_nodes_weight(vec.iter().copied(), g),
+        || get_nodes_weight(g.node_indices(), g, node_score),
+        |vec| get_nodes_weight(vec.iter().copied(), g, node_score),
     );
 
-    //Sort nodes based on connected edge weight and then alphabetically
+    //Sort nodes based on connected edge weight (NaN as lowest) and then alphabetically (or randomly)
+    let weight_key = |weight: f32| {
+        if weight.is_nan() {
+            f32::NEG_INFINITY
+        } else {
+            weight
+        }
+    };
     nodes_weight.sort_by(|a, b| {
-        b.1.partial_cmp(&a.1)
-            .unwrap()
-            .then(g.node_weight(a.0).cmp(&g.node_weight(b.0)))
+        weight_key(b.1)
+            .total_cmp(&weight_key(a.1))
+            .then_with(|| match seed {
+                Some(seed) => {
+                    random_key(seed, a.0.index() as u64).cmp(&random_key(seed, b.0.index() as u64))
+                }
+                None => g.node_weight(a.0).cmp(&g.node_weight(b.0)),
+            })
     });
 
     trace!("Sorted node weights: {:?}", nodes_weight);
@@ -249,6 +2010,170 @@ pub fn find_heaviest_node(
     nodes_weight[0]
 }
 
+/// Prune graph until no edges are left, returning the excluded nodes.
+///
+/// Nodes in `nodes_keep` are never excluded, so all their neighbors are excluded before pruning.
+pub fn graph_prune(
+    graph: &mut Graph,
+    mode: u8,
+    keep_heavy: bool,
+    nodes_keep: &HashSet<NodeIndex<GraphIdx>>,
+    seed: Option<u64>,
+    node_score: &NodeScore,
+) -> Vec<String> {
+    // Store deleted nodes
+    let mut nodes_excl = Vec::<String>::new();
+
+    // Exclude neighbors of nodes to keep
+    if !nodes_keep.is_empty() {
+        let nodes_neighb: HashSet<NodeIndex<GraphIdx>> = nodes_keep
+            .iter()
+            .filter(|node| graph.contains_node(**node))
+            .flat_map(|node| graph.neighbors(*node))
+            .filter(|node| !nodes_keep.contains(node))
+            .collect();
+        for node_neighb in nodes_neighb {
+            nodes_excl.push(graph.node_weight(node_neighb).unwrap().to_string());
+            graph.remove_node(node_neighb);
+        }
+
+        // Edges between nodes to keep cannot be pruned
+        let n_edges = graph.edge_count();
+        graph.retain_edges(|g, e| {
+            let (node1, node2) = g.edge_endpoints(e).unwrap();
+            !(nodes_keep.contains(&node1) && nodes_keep.contains(&node2))
+        });
+        if graph.edge_count() < n_edges {
+            warn!(
+                "{} edge(s) between nodes to keep were ignored",
+                n_edges - graph.edge_count()
+            );
+        }
+        debug!(
+            "Excluded {} neighbor(s) of {} node(s) to keep",
+            nodes_excl.len(),
+            nodes_keep.len()
+        );
+    }
+
+    let mut n_iters = 0;
+    let mut delta_n_nodes = 0;
+    let mut delta_n_edges = graph.edge_count() as u64;
+
+    // Initialize progress bar
+    let prune_span = info_span!("prune");
+    prune_span.pb_set_length(delta_n_edges);
+    prune_span.pb_set_style(
+        &ProgressStyle::with_template(
+            "{bar:50} {pos:>10}/{len} edges pruned in {elapsed} ({per_sec:>0}) {msg}",
+        )
+        .unwrap(),
+    );
+    let prune_span_enter = prune_span.enter();
+
+    while graph.edge_count() > 0 {
+        // Find heaviest nodes
+        let nodes_heavy = if mode == 1 {
+            kosaraju_scc(&*graph)
+                .par_iter()
+                .filter(|x| x.len() > 1)
+                .map(|x| find_heaviest_node(graph, Some(x), seed, node_score))
+                .collect()
+        } else {
+            vec![find_heaviest_node(graph, None, seed, node_score)]
+        };
+        trace!("{:?}", nodes_heavy);
+
+        // Process heaviest node
+        for (node_heavy, _node_heavy_weight) in &nodes_heavy {
+            if keep_heavy {
+                let mut nodes_del = graph.neighbors_undirected(*node_heavy).detach();
+                while let Some(node_neighb) = nodes_del.next_node(graph) {
+                    nodes_excl.push(graph.node_weight(node_neighb).unwrap().to_string());
+                    graph.remove_node(node_neighb);
+                    delta_n_nodes += 1;
+                }
+            } else {
+                nodes_excl.push(graph.node_weight(*node_heavy).unwrap().to_string());
+                graph.remove_node(*node_heavy);
+                delta_n_nodes += 1;
+            }
+        }
+        n_iters += 1;
+
+        // Update progress bar
+        prune_span.pb_inc(delta_n_edges - graph.edge_count() as u64);
+        delta_n_edges = graph.edge_count() as u64;
+        if enabled!(Level::DEBUG) {
+            prune_span.pb_set_message(&format!(
+                "from {0} nodes ({1:.2}/s) [{2} iters.]",
+                delta_n_nodes,
+                delta_n_nodes as f32 / prune_span.pb_elapsed().as_secs_f32(),
+                n_iters,
+            ));
+        }
+    }
+    std::mem::drop(prune_span_enter);
+    std::mem::drop(prune_span);
+
+    debug!(
+        "Final graph has {0} nodes with {1} edges",
+        graph.node_count(),
+        graph.edge_count()
+    );
+
+    nodes_excl
+}
+
+/// Sweep indices ordered from strictest (most edges) to loosest.
+pub fn sweep_order(edges_sweep: &[u64], n_sweeps: usize) -> Vec<usize> {
+    let mut order: Vec<usize> = (0..n_sweeps).collect();
+    order.sort_by_cached_key(|sweep_idx| {
+        std::cmp::Reverse(
+            edges_sweep
+                .iter()
+                .filter(|edge_sweep| *edge_sweep >> sweep_idx & 1 == 1)
+                .count(),
+        )
+    });
+    order
+}
+
+/// Strictest sweep at which each node is kept (i.e. first kept), given the kept sets of sweeps run in `order` (strictest first).
+pub fn sweep_strictest(
+    sweep_kept: &[HashSet<NodeIndex<GraphIdx>>],
+    order: &[usize],
+) -> HashMap<NodeIndex<GraphIdx>, usize> {
+    let mut nodes_sweep = HashMap::new();
+    for sweep_idx in order {
+        for node in &sweep_kept[*sweep_idx] {
+            nodes_sweep.entry(*node).or_insert(*sweep_idx);
+        }
+    }
+    nodes_sweep
+}
+
+/// Keep only the edges that pass the sweep filter with index `sweep_idx`.
+pub fn graph_sweep(graph: &mut Graph, edges_sweep: &[u64], sweep_idx: usize) {
+    graph.retain_edges(|_, e| edges_sweep[e.index()] >> sweep_idx & 1 == 1);
+}
+
+/// Add uniform noise (in `[-jitter, jitter]`) to all edge weights.
+pub fn graph_jitter(graph: &mut Graph, jitter: f32, seed: u64) {
+    for (i, weight) in graph.edge_weights_mut().enumerate() {
+        let noise = (random_key(seed, i as u64) >> 11) as f64 / (1u64 << 53) as f64;
+        *weight += ((2.0 * noise - 1.0) * jitter as f64) as f32;
+    }
+}
+
+/// Pseudo-random number derived from `seed` and `x` (SplitMix64), independent of evaluation order.
+fn random_key(seed: u64, x: u64) -> u64 {
+    let mut z = seed.wrapping_add(x.wrapping_add(1).wrapping_mul(0x9E3779B97F4A7C15));
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D049BB133111EB);
+    z ^ (z >> 31)
+}
+
 fn round(x: f32, decimals: i32) -> f32 {
     let y = 10f32.powi(decimals);
     (x * y).round() / y
@@ -272,60 +2197,628 @@ mod tests {
 
     #[test]
     fn test_graph_read() {
-        let (graph, _graph_idx) = graph_read(
+        let (graph, _graph_idx, _edges_sweep, _meta) = graph_read(
             BufReader::new(File::open("test/example.tsv").expect("cannot open input file")),
-            true,
-            "r2".to_string(),
-            Some("r2 > 0.2".to_string()),
-            false,
-            4,
+            &ReadOpts {
+                has_header: true,
+                weight_field: "r2".to_string(),
+                weight_filter: Some("r2 > 0.2".to_string()),
+                ..Default::default()
+            },
         );
-        assert_eq!(graph.is_directed(), false);
+        assert!(!graph.is_directed());
         assert_eq!(graph.node_count(), 65);
         assert_eq!(graph.edge_count(), 104);
     }
 
+    #[test]
+    fn test_graph_read_missing() {
+        let input = "A\tB\t0.5\nB\tC\tNA\nC\tD\t\nD\tE\tabc\nE\tF\nF\tG\t0.9\n";
+        let read = |missing, invalid| {
+            let (graph, graph_idx, _edges_sweep, _meta) = graph_read(
+                input.as_bytes(),
+                &ReadOpts {
+                    missing,
+                    invalid,
+                    ..Default::default()
+                },
+            );
+            (graph, graph_idx)
+        };
+        let (graph, graph_idx) = read(MissingPolicy::Skip, InvalidPolicy::Skip);
+        assert_eq!(graph.edge_count(), 2);
+        assert_eq!(graph.edges(graph_idx["C"]).count(), 0);
+        assert_eq!(graph.edges(graph_idx["F"]).count(), 1);
+
+        let (graph, graph_idx) = read(MissingPolicy::Zero, InvalidPolicy::Skip);
+        assert_eq!(graph.edge_count(), 4);
+        let edge = graph.find_edge(graph_idx["B"], graph_idx["C"]).unwrap();
+        assert_eq!(graph[edge], 0.0);
+    }
+
+    #[test]
+    fn test_graph_read_header() {
+        let data = "A\tB\t100\t0.5\nB\tC\t200\t0.3\n";
+        let read = |input: &str, opts: ReadOpts| {
+            let (graph, _graph_idx, _edges_sweep, meta) = graph_read(input.as_bytes(), &opts);
+            (graph.edge_count(), meta.header)
+        };
+        let header = |names: &[&str]| names.iter().map(|h| h.to_string()).collect::<Vec<_>>();
+
+        // No header (numeric weight on first line)
+        let (n_edges, meta_header) = read(data, ReadOpts::default());
+        assert_eq!(n_edges, 2);
+        assert_eq!(meta_header[2], "column_3");
+
+        // Header detected (from weight field name, or non-numeric weight value), also if prefixed by '#'
+        for input in [
+            format!("site1\tsite2\tdist\tr2\n{data}"),
+            format!("##source=test\n#site1\tsite2\tdist\tr2\n{data}"),
+        ] {
+            let (n_edges, meta_header) = read(
+                &input,
+                ReadOpts {
+                    weight_field: "r2".to_string(),
+                    ..Default::default()
+                },
+            );
+            assert_eq!(n_edges, 2);
+            assert_eq!(meta_header, header(&["site1", "site2", "dist", "r2"]));
+        }
+        let mode = ReadOpts {
+            weight_field: "column_4".to_string(),
+            ..Default::default()
+        }
+        .header_mode();
+        assert_eq!(
+            mode.resolve(None, "site1\tsite2\tdist\tr2", Delimiter::Auto)
+                .0,
+            HeaderLine::First
+        );
+        assert_eq!(
+            mode.resolve(None, "A\tB\t100\tNA", Delimiter::Auto).0,
+            HeaderLine::None
+        );
+        assert_eq!(
+            mode.resolve(Some(" comment"), "A\tB\t100\t0.5", Delimiter::Auto)
+                .0,
+            HeaderLine::None
+        );
+
+        // Column names of header-less input
+        let (n_edges, meta_header) = read(
+            &format!("# comment\n{data}"),
+            ReadOpts {
+                column_names: Some(header(&["site1", "site2", "dist", "r2"])),
+                weight_field: "r2".to_string(),
+                weight_filter: Some("dist < 150".to_string()),
+                ..Default::default()
+            },
+        );
+        assert_eq!(n_edges, 1);
+        assert_eq!(meta_header, header(&["site1", "site2", "dist", "r2"]));
+    }
+
+    #[test]
+    fn test_graph_read_strings() {
+        let input = "site1\tsite2\tchr_a\tchr_b\ttype\tnote\tr2\n\
+                     A\tB\tchr1\tchr1\tsnp\tx y\t0.5\n\
+                     B\tC\tchr1\tchr2\tsnp\t-\t0.6\n\
+                     C\tD\tchr2\tchr2\tindel\t-\t0.7\n\
+                     D\tE\tchr2\tchr2\tsnp\t-\t0.8\n";
+        let opts = ReadOpts {
+            has_header: true,
+            weight_field: "r2".to_string(),
+            weight_filter: Some("chr_a == chr_b && type != 'indel'".to_string()),
+            ..Default::default()
+        };
+        let (graph, graph_idx, _edges_sweep, _meta) = graph_read(input.as_bytes(), &opts);
+        assert_eq!(graph.edge_count(), 2);
+        assert!(graph.find_edge(graph_idx["A"], graph_idx["B"]).is_some());
+        assert!(graph.find_edge(graph_idx["D"], graph_idx["E"]).is_some());
+
+        // Strings cannot be ordered, so columns compared with '>' must be numeric
+        let opts = ReadOpts {
+            has_header: true,
+            weight_field: "r2".to_string(),
+            weight_filter: Some("chr_a > 0".to_string()),
+            invalid: InvalidPolicy::Skip,
+            ..Default::default()
+        };
+        let (graph, _graph_idx, _edges_sweep, _meta) = graph_read(input.as_bytes(), &opts);
+        assert_eq!(graph.edge_count(), 0);
+
+        // Values compared as strings are parsed like string literals (not rounded to the weight precision)
+        let input =
+            "site1\tsite2\tid\tr2\nA\tB\t16777217\t0.5\nB\tC\t16777216\t0.6\nC\tD\t0.00001\t0.7\n";
+        for (filter, n_edges) in [
+            ("id == '16777217'", 1),
+            ("id != '16777217'", 2),
+            ("id == 16777216", 1),
+            ("id == '0.00001'", 1),
+        ] {
+            let opts = ReadOpts {
+                has_header: true,
+                weight_field: "r2".to_string(),
+                weight_filter: Some(filter.to_string()),
+                ..Default::default()
+            };
+            let (graph, _graph_idx, _edges_sweep, _meta) = graph_read(input.as_bytes(), &opts);
+            assert_eq!(graph.edge_count(), n_edges, "{filter}");
+        }
+    }
+
+    #[test]
+    fn test_graph_read_node_vars() {
+        let read = |filter: &str, node_attrs: Option<NodeAttrs>| {
+            graph_read(
+                BufReader::new(File::open("test/example.tsv").expect("cannot open input file")),
+                &ReadOpts {
+                    has_header: true,
+                    weight_field: "r2".to_string(),
+                    weight_filter: Some(filter.to_string()),
+                    node_attrs,
+                    ..Default::default()
+                },
+            )
+            .0
+        };
+        assert_eq!(
+            read(
+                "abs(node1.pos - node2.pos) < 10000 && node1.chr == node2.chr",
+                None
+            )
+            .edge_count(),
+            read("dist < 10000", None).edge_count()
+        );
+
+        let node_attrs = NodeAttrs {
+            header: vec!["maf".to_string()],
+            values: HashMap::from([
+                ("NC_046966.1:7361".to_string(), vec![0.1]),
+                ("NC_046966.1:13594".to_string(), vec![0.2]),
+            ]),
+        };
+        let graph = read("node1.maf > 0.05 && node2.maf > 0.05", Some(node_attrs));
+        assert_eq!(graph.edge_count(), 1);
+    }
+
+    #[test]
+    fn test_graph_read_weight_expr() {
+        let (graph, graph_idx, _edges_sweep, _meta) = graph_read(
+            BufReader::new(File::open("test/example.tsv").expect("cannot open input file")),
+            &ReadOpts {
+                has_header: true,
+                weight_field: "r2".to_string(),
+                weight_expr: Some("abs(D) * (dist < 10000)".to_string()),
+                weight_filter: Some("r2 > 0.2".to_string()),
+                ..Default::default()
+            },
+        );
+        assert_eq!(graph.edge_count(), 104);
+        let edge = graph
+            .find_edge(
+                graph_idx["NC_046966.1:7361"],
+                graph_idx["NC_046966.1:13594"],
+            )
+            .unwrap();
+        assert_eq!(graph[edge], 0.0296);
+        let edge = graph
+            .find_edge(
+                graph_idx["NC_046966.1:13594"],
+                graph_idx["NC_046966.1:45391"],
+            )
+            .unwrap();
+        assert_eq!(graph[edge], 0.0);
+    }
+
+    #[test]
+    fn test_graph_read_duplicates() {
+        let input = "A\tB\t0.5\nB\tA\t0.7\nA\tB\t0.3\nA\tA\t1.0\nB\tC\t0.2\n";
+        for (duplicates, weight) in [
+            (DupPolicy::First, 0.5),
+            (DupPolicy::Last, 0.3),
+            (DupPolicy::Max, 0.7),
+            (DupPolicy::Min, 0.3),
+            (DupPolicy::Mean, 0.5),
+            (DupPolicy::Sum, 1.5),
+        ] {
+            let (graph, graph_idx, _edges_sweep, _meta) = graph_read(
+                input.as_bytes(),
+                &ReadOpts {
+                    duplicates,
+                    ..Default::default()
+                },
+            );
+            assert_eq!(graph.edge_count(), 2);
+            let edge = graph.find_edge(graph_idx["A"], graph_idx["B"]).unwrap();
+            assert_eq!(graph[edge], weight);
+        }
+
+        // All occurrences are kept by default
+        let (graph, graph_idx, _edges_sweep, _meta) =
+            graph_read(input.as_bytes(), &ReadOpts::default());
+        assert_eq!(graph.edge_count(), 4);
+        assert_eq!(
+            graph
+                .edges_connecting(graph_idx["A"], graph_idx["B"])
+                .count(),
+            3
+        );
+
+        // Duplicates are merged before filtering, so filters apply to the merged edge
+        for (duplicates, filter, n_edges) in [
+            (DupPolicy::First, "column_3 > 0.6", 0),
+            (DupPolicy::Max, "column_3 > 0.6", 1),
+            (DupPolicy::Mean, "column_3 > 0.4", 1),
+            (DupPolicy::Mean, "column_3 > 0.6", 0),
+        ] {
+            let (graph, _graph_idx, _edges_sweep, _meta) = graph_read(
+                input.as_bytes(),
+                &ReadOpts {
+                    duplicates,
+                    weight_filter: Some(filter.to_string()),
+                    ..Default::default()
+                },
+            );
+            assert_eq!(graph.edge_count(), n_edges);
+        }
+        let (graph, _graph_idx, edges_sweep, _meta) = graph_read(
+            input.as_bytes(),
+            &ReadOpts {
+                duplicates: DupPolicy::Sum,
+                sweep_filters: vec!["column_3 > 1".to_string(), "column_3 > 0.1".to_string()],
+                ..Default::default()
+            },
+        );
+        assert_eq!(graph.edge_count(), 2);
+        let sweeps: Vec<u64> = graph
+            .edge_indices()
+            .map(|edge| edges_sweep[edge.index()])
+            .collect();
+        assert_eq!(sweeps, vec![0b11, 0b10]);
+
+        // Self-loops
+        let (graph, graph_idx, _edges_sweep, _meta) = graph_read(
+            input.as_bytes(),
+            &ReadOpts {
+                duplicates: DupPolicy::First,
+                self_loops: SelfLoopPolicy::Keep,
+                ..Default::default()
+            },
+        );
+        assert_eq!(graph.edge_count(), 3);
+        assert!(graph.find_edge(graph_idx["A"], graph_idx["A"]).is_some());
+
+        // Duplicated self-loops are also merged
+        let (graph, graph_idx, _edges_sweep, _meta) = graph_read(
+            format!("{input}A\tA\t3.0\n").as_bytes(),
+            &ReadOpts {
+                duplicates: DupPolicy::Sum,
+                self_loops: SelfLoopPolicy::Keep,
+                ..Default::default()
+            },
+        );
+        assert_eq!(graph.edge_count(), 3);
+        let edge = graph.find_edge(graph_idx["A"], graph_idx["A"]).unwrap();
+        assert_eq!(graph[edge], 4.0);
+
+        // With a weight expression, numeric columns are merged and the weight is evaluated on them (as are filters)
+        let input = "A\tB\t0.2\t1\nB\tA\t0.8\t3\nB\tC\t0.5\t2\n";
+        for (filter, n_edges) in [
+            ("column_3 * column_4 >= 1", 2),
+            ("column_3 * column_4 > 1", 0),
+        ] {
+            let (graph, graph_idx, _edges_sweep, _meta) = graph_read(
+                input.as_bytes(),
+                &ReadOpts {
+                    duplicates: DupPolicy::Mean,
+                    weight_expr: Some("column_3 * column_4".to_string()),
+                    weight_filter: Some(filter.to_string()),
+                    ..Default::default()
+                },
+            );
+            assert_eq!(graph.edge_count(), n_edges);
+            if let Some(edge) = graph.find_edge(graph_idx["A"], graph_idx["B"]) {
+                assert_eq!(graph[edge], 1.0);
+            }
+        }
+    }
+
+    #[test]
+    fn test_graph_read_non_finite() {
+        let input = "A\tB\t0.5\t1\nB\tC\t-0.7\t1\nC\tD\tinf\t1\nD\tE\tnan\t1\nE\tF\t0.1\t-inf\n";
+        let read = |negative, non_finite| {
+            graph_read(
+                input.as_bytes(),
+                &ReadOpts {
+                    weight_filter: Some("column_4 > 0".to_string()),
+                    negative,
+                    non_finite,
+                    ..Default::default()
+                },
+            )
+        };
+
+        // Default
+        let (graph, graph_idx, _edges_sweep, _meta) =
+            read(NegativePolicy::Keep, NonFinitePolicy::Keep);
+        assert_eq!(graph.edge_count(), 3);
+        let edge = graph.find_edge(graph_idx["C"], graph_idx["D"]).unwrap();
+        assert_eq!(graph[edge], f32::INFINITY);
+        let edge = graph.find_edge(graph_idx["B"], graph_idx["C"]).unwrap();
+        assert_eq!(graph[edge], -0.7);
+
+        // Skip non-finite
+        let (graph, graph_idx, _edges_sweep, _meta) =
+            read(NegativePolicy::Abs, NonFinitePolicy::Skip);
+        assert_eq!(graph.edge_count(), 2);
+        let edge = graph.find_edge(graph_idx["B"], graph_idx["C"]).unwrap();
+        assert_eq!(graph[edge], 0.7);
+
+        // Cap non-finite
+        let (graph, graph_idx, _edges_sweep, _meta) =
+            read(NegativePolicy::Zero, NonFinitePolicy::Cap);
+        assert_eq!(graph.edge_count(), 3);
+        let edge = graph.find_edge(graph_idx["C"], graph_idx["D"]).unwrap();
+        assert_eq!(graph[edge], 1.0);
+        let edge = graph.find_edge(graph_idx["B"], graph_idx["C"]).unwrap();
+        assert_eq!(graph[edge], 0.0);
+    }
+
+    #[test]
+    fn test_graph_read_node_fields() {
+        let input = "CHR_A\tBP_A\tSNP_A\tCHR_B\tBP_B\tSNP_B\tR2\n1\t100\trs1\t1\t200\trs2\t0.5\n1\t200\trs2\t1\t300\trs3\t0.3\n";
+        let (graph, graph_idx, _edges_sweep, _meta) = graph_read(
+            input.as_bytes(),
+            &ReadOpts {
+                has_header: true,
+                node_fields: Some(vec!["SNP_A".to_string(), "SNP_B".to_string()]),
+                weight_field: "R2".to_string(),
+                weight_filter: Some("BP_B - BP_A < 150".to_string()),
+                ..Default::default()
+            },
+        );
+        assert_eq!(graph.node_count(), 3);
+        assert_eq!(graph.edge_count(), 2);
+        assert!(graph
+            .find_edge(graph_idx["rs1"], graph_idx["rs2"])
+            .is_some());
+
+        // Node ID columns can also be used in filters
+        let input = format!("{input}1\t300\trs3\t2\t100\trs4\t0.9\n");
+        let (graph, graph_idx, _edges_sweep, _meta) = graph_read(
+            input.as_bytes(),
+            &ReadOpts {
+                has_header: true,
+                node_fields: Some(vec!["CHR_A:BP_A".to_string(), "CHR_B:BP_B".to_string()]),
+                weight_field: "R2".to_string(),
+                weight_filter: Some("CHR_A == CHR_B".to_string()),
+                ..Default::default()
+            },
+        );
+        assert_eq!(graph.node_count(), 4);
+        assert_eq!(graph.edge_count(), 2);
+        assert!(graph
+            .find_edge(graph_idx["1:200"], graph_idx["1:300"])
+            .is_some());
+    }
+
+    #[test]
+    fn test_delimiter() {
+        assert_eq!("tab".parse::<Delimiter>(), Ok(Delimiter::Char('\t')));
+        assert_eq!(";".parse::<Delimiter>(), Ok(Delimiter::Char(';')));
+        assert!("ab".parse::<Delimiter>().is_err());
+        assert_eq!(
+            Delimiter::Whitespace.split("  CHR_A   BP_A SNP_A "),
+            vec!["CHR_A", "BP_A", "SNP_A"]
+        );
+        assert_eq!(
+            Delimiter::Char(',').split("\"a,b\",c,\"say \"\"hi\"\"\""),
+            vec!["a,b", "c", "say \"hi\""]
+        );
+        assert_eq!(Delimiter::Auto.split("a b,c d"), vec!["a b", "c d"]);
+    }
+
+    #[test]
+    fn test_graph_read_delimiter() {
+        for input in [
+            "# comment\nA\tB\t0.5\nB\tC\t0.3\n",
+            "\"A\",\"B\",0.5\n\nB,C,0.3\n",
+            "  A   B  0.5\n  B   C  0.3\n",
+        ] {
+            let (graph, graph_idx, _edges_sweep, _meta) =
+                graph_read(input.as_bytes(), &ReadOpts::default());
+            assert_eq!(graph.edge_count(), 2);
+            let edge = graph.find_edge(graph_idx["A"], graph_idx["B"]).unwrap();
+            assert_eq!(graph[edge], 0.5);
+        }
+    }
+
+    #[test]
+    fn test_graph_read_plink() {
+        // PLINK format must be requested explicitly
+        assert_eq!(
+            InputFormat::detect(Some(Path::new("data/chr1.ld.gz"))),
+            InputFormat::Tsv
+        );
+        assert_eq!(InputFormat::detect(None), InputFormat::Tsv);
+
+        let input = " CHR_A         BP_A        SNP_A  CHR_B         BP_B        SNP_B           R2 \n     1          100          rs1      1          200          rs2          0.5 \n     1          200          rs2      1          300          rs3     0.300001 \n";
+        let (graph, graph_idx, _edges_sweep, meta) = graph_read(
+            input.as_bytes(),
+            &InputFormat::Plink.read_opts(ReadOpts {
+                weight_field: InputFormat::Plink.weight_field().to_string(),
+                ..Default::default()
+            }),
+        );
+        assert_eq!(graph.node_count(), 3);
+        let edge = graph.find_edge(graph_idx["rs2"], graph_idx["rs3"]).unwrap();
+        assert_eq!(graph[edge], 0.3);
+        assert_eq!(
+            meta.nodes_pos["rs3"],
+            NodePos {
+                chr: "1".to_string(),
+                bp: 300
+            }
+        );
+    }
+
+    #[test]
+    fn test_graph_layers() {
+        let layers = || {
+            ["A\tB\t0.5\nB\tC\t0.2\n", "A\tB\t0.3\nC\tD\t0.8\n"]
+                .iter()
+                .map(|input| graph_read(input.as_bytes(), &ReadOpts::default()).0)
+                .collect::<Vec<Graph>>()
+        };
+        let weight = |graph: &Graph, a: &str, b: &str| {
+            let idx = |label: &str| graph.node_indices().find(|n| graph[*n] == label).unwrap();
+            graph[graph.find_edge(idx(a), idx(b)).unwrap()]
+        };
+
+        let graph = graph_layers(layers(), LayerCombine::Max, &[], None, 4);
+        assert_eq!(graph.node_count(), 4);
+        assert_eq!(graph.edge_count(), 3);
+        assert_eq!(weight(&graph, "A", "B"), 0.5);
+        assert_eq!(weight(&graph, "C", "D"), 0.8);
+
+        let graph = graph_layers(layers(), LayerCombine::Mean, &[], None, 4);
+        assert_eq!(weight(&graph, "A", "B"), 0.4);
+        assert_eq!(weight(&graph, "B", "C"), 0.2);
+
+        let graph = graph_layers(layers(), LayerCombine::Mean, &[], Some(0.0), 4);
+        assert_eq!(weight(&graph, "B", "C"), 0.1);
+
+        let graph = graph_layers(layers(), LayerCombine::Min, &[], Some(0.0), 4);
+        assert_eq!(weight(&graph, "C", "D"), 0.0);
+
+        let graph = graph_layers(layers(), LayerCombine::WeightedMean, &[1.0, 3.0], None, 4);
+        assert_eq!(weight(&graph, "A", "B"), 0.35);
+
+        // Filters apply to the combined weight
+        let mut graph = graph_layers(layers(), LayerCombine::Mean, &[], Some(0.0), 4);
+        let edges_sweep = graph_filter(
+            &mut graph,
+            &ReadOpts {
+                weight_filter: Some("column_3 > 0.2".to_string()),
+                sweep_filters: vec!["column_3 > 0.3".to_string(), "column_3 > 0".to_string()],
+                ..Default::default()
+            },
+        );
+        assert_eq!(graph.edge_count(), 2);
+        assert_eq!(weight(&graph, "A", "B"), 0.4);
+        assert_eq!(weight(&graph, "C", "D"), 0.4);
+        assert!(graph
+            .edge_indices()
+            .all(|edge| edges_sweep[edge.index()] == 0b11));
+        assert!(graph_filter_check(&ReadOpts {
+            weight_filter: Some("column_3 > 0.2 && column_4 > 0".to_string()),
+            ..Default::default()
+        })
+        .is_err());
+
+        // Duplicates are merged within each layer
+        let layers: Vec<Graph> = ["A\tB\t0.5\nB\tA\t0.1\n", "A\tB\t0.3\n"]
+            .iter()
+            .map(|input| {
+                let opts = ReadOpts {
+                    duplicates: DupPolicy::Min,
+                    ..Default::default()
+                };
+                graph_read(input.as_bytes(), &opts).0
+            })
+            .collect();
+        let graph = graph_layers(layers, LayerCombine::Max, &[], None, 4);
+        assert_eq!(weight(&graph, "A", "B"), 0.3);
+    }
+
     #[test]
     fn test_graph_subset() {
-        let (mut graph, _graph_idx) = graph_read(
+        let (mut graph, _graph_idx, _edges_sweep, _meta) = graph_read(
             BufReader::new(File::open("test/example.tsv").expect("cannot open input file")),
-            true,
-            "r2".to_string(),
-            Some("r2 > 0.2".to_string()),
-            false,
-            4,
+            &ReadOpts {
+                has_header: true,
+                weight_field: "r2".to_string(),
+                weight_filter: Some("r2 > 0.2".to_string()),
+                ..Default::default()
+            },
         );
-        assert_eq!(graph.is_directed(), false);
+        assert!(!graph.is_directed());
         graph_subset(&mut graph, PathBuf::from("test/example.subset"));
         assert_eq!(graph.node_count(), 11);
         assert_eq!(graph.edge_count(), 22);
     }
 
+    #[test]
+    fn test_graph_nodes() {
+        let (mut graph, _graph_idx, _edges_sweep, _meta) = graph_read(
+            BufReader::new(File::open("test/example.tsv").expect("cannot open input file")),
+            &ReadOpts {
+                has_header: true,
+                weight_field: "r2".to_string(),
+                weight_filter: Some("r2 > 0.2".to_string()),
+                ..Default::default()
+            },
+        );
+        let path =
+            std::env::temp_dir().join(format!("prune_graph_test_{}.pos", std::process::id()));
+        let subset = std::fs::read_to_string("test/example.subset").unwrap();
+        std::fs::write(
+            &path,
+            format!("chr\tpos\n{}NC_046966.1:1\n", subset).replace(':', "\t"),
+        )
+        .unwrap();
+        let (nodes, nodes_pos) = nodes_read(&path);
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(nodes.len(), 12);
+        assert_eq!(nodes[0], "NC_046966.1:31878");
+        assert_eq!(
+            nodes_pos["NC_046966.1:1"],
+            NodePos {
+                chr: "NC_046966.1".to_string(),
+                bp: 1
+            }
+        );
+
+        let (nodes_unknown, n_edges_unknown) = graph_nodes_unknown(&graph, &nodes);
+        assert_eq!(nodes_unknown.len(), graph.node_count() - 11);
+        assert_eq!(n_edges_unknown, graph.edge_count() - 22);
+        assert_eq!(graph_add_nodes(&mut graph, nodes), 1);
+        assert_eq!(graph.node_count(), 66);
+    }
+
     #[test]
     fn test_find_all_edges() {
-        let (graph, graph_idx) = graph_read(
+        let (graph, graph_idx, _edges_sweep, _meta) = graph_read(
             BufReader::new(File::open("test/example.tsv").expect("cannot open input file")),
-            true,
-            "r2".to_string(),
-            Some("r2 > 0.2".to_string()),
-            false,
-            4,
+            &ReadOpts {
+                has_header: true,
+                weight_field: "r2".to_string(),
+                weight_filter: Some("r2 > 0.2".to_string()),
+                ..Default::default()
+            },
         );
         assert_eq!(graph.edges(graph_idx["NC_046966.1:26131"]).count(), 6);
     }
 
     #[test]
     fn test_get_node_weight() {
-        let (graph, graph_idx) = graph_read(
+        let (graph, graph_idx, _edges_sweep, _meta) = graph_read(
             BufReader::new(File::open("test/example.tsv").expect("cannot open input file")),
-            true,
-            "r2".to_string(),
-            Some("r2 > 0.2".to_string()),
-            false,
-            4,
+            &ReadOpts {
+                has_header: true,
+                weight_field: "r2".to_string(),
+                weight_filter: Some("r2 > 0.2".to_string()),
+                ..Default::default()
+            },
         );
 
-        let nodes_weight = get_node_weight(graph_idx["NC_046966.1:12856"], &graph);
+        let nodes_weight = get_node_weight(
+            graph_idx["NC_046966.1:12856"],
+            &graph,
+            &NodeScore::default(),
+        );
         assert_eq!(
             graph.node_weight(nodes_weight.0).unwrap(),
             "NC_046966.1:12856"
@@ -335,16 +2828,17 @@ mod tests {
 
     #[test]
     fn test_get_nodes_weight() {
-        let (graph, _graph_idx) = graph_read(
+        let (graph, _graph_idx, _edges_sweep, _meta) = graph_read(
             BufReader::new(File::open("test/example.tsv").expect("cannot open input file")),
-            true,
-            "r2".to_string(),
-            Some("r2 > 0.2".to_string()),
-            false,
-            4,
+            &ReadOpts {
+                has_header: true,
+                weight_field: "r2".to_string(),
+                weight_filter: Some("r2 > 0.2".to_string()),
+                ..Default::default()
+            },
         );
 
-        let nodes_weight = get_nodes_weight(graph.node_indices(), &graph);
+        let nodes_weight = get_nodes_weight(graph.node_indices(), &graph, &NodeScore::default());
         assert_eq!(
             graph.node_weight(nodes_weight[0].0).unwrap(),
             "NC_046966.1:12856"
@@ -372,19 +2866,69 @@ mod tests {
         assert_eq!(nodes_weight[4].1, 0.4336);
     }
 
+    #[test]
+    fn test_node_score() {
+        let (graph, graph_idx, _edges_sweep, _meta) = graph_read(
+            BufReader::new(File::open("test/example.tsv").expect("cannot open input file")),
+            &ReadOpts {
+                has_header: true,
+                weight_field: "r2".to_string(),
+                weight_filter: Some("r2 > 0.2".to_string()),
+                ..Default::default()
+            },
+        );
+        let node_idx = graph_idx["NC_046966.1:13594"];
+
+        let node_score = NodeScore::new("count", None).unwrap();
+        assert_eq!(get_node_weight(node_idx, &graph, &node_score).1, 3.0);
+        let node_score = NodeScore::new("count * max", None).unwrap();
+        assert_eq!(
+            round(get_node_weight(node_idx, &graph, &node_score).1, 4),
+            2.9976
+        );
+        let node_score = NodeScore::new("sum - 0.5 * count", None).unwrap();
+        assert_eq!(
+            round(get_node_weight(node_idx, &graph, &node_score).1, 4),
+            0.0552
+        );
+
+        let node_attrs = NodeAttrs {
+            header: vec!["maf".to_string()],
+            values: HashMap::from([("NC_046966.1:13594".to_string(), vec![0.25])]),
+        };
+        let node_score = NodeScore::new("sum * maf", Some(&node_attrs)).unwrap();
+        assert_eq!(
+            round(get_node_weight(node_idx, &graph, &node_score).1, 4),
+            0.3888
+        );
+        assert!(NodeScore::new("sum * maf", None).is_err());
+
+        // Nodes missing from the attributes file have NaN attributes
+        let node_idx = graph_idx["NC_046966.1:12856"];
+        assert!(get_node_weight(node_idx, &graph, &node_score).1.is_nan());
+
+        // NaN scores are the lowest when sorting
+        let node_score = NodeScore::new("maf", Some(&node_attrs)).unwrap();
+        let (node_heaviest, node_weight) = find_heaviest_node(&graph, None, None, &node_score);
+        assert_eq!(graph[node_heaviest], "NC_046966.1:13594");
+        assert_eq!(node_weight, 0.25);
+    }
+
     #[test]
     fn test_find_heaviest_node() {
-        let (mut graph, graph_idx) = graph_read(
+        let (mut graph, graph_idx, _edges_sweep, _meta) = graph_read(
             BufReader::new(File::open("test/example.tsv").expect("cannot open input file")),
-            true,
-            "r2".to_string(),
-            Some("r2 > 0.2".to_string()),
-            false,
-            4,
+            &ReadOpts {
+                has_header: true,
+                weight_field: "r2".to_string(),
+                weight_filter: Some("r2 > 0.2".to_string()),
+                ..Default::default()
+            },
         );
 
         // Round #1
-        let (node_heaviest, node_weight) = find_heaviest_node(&graph, None);
+        let (node_heaviest, node_weight) =
+            find_heaviest_node(&graph, None, None, &NodeScore::default());
         assert_eq!(
             graph.node_weight(node_heaviest).unwrap(),
             "NC_046966.1:10729"
@@ -392,7 +2936,8 @@ mod tests {
         assert_eq!(round(node_weight, 4), f32::INFINITY);
         // Round #2
         graph.remove_node(graph_idx["NC_046966.1:10729"]);
-        let (node_heaviest, node_weight) = find_heaviest_node(&graph, None);
+        let (node_heaviest, node_weight) =
+            find_heaviest_node(&graph, None, None, &NodeScore::default());
         assert_eq!(
             graph.node_weight(node_heaviest).unwrap(),
             "NC_046966.1:26131"
@@ -400,7 +2945,8 @@ mod tests {
         assert_eq!(round(node_weight, 4), f32::INFINITY);
         // Round #3
         graph.remove_node(graph_idx["NC_046966.1:26131"]);
-        let (node_heaviest, node_weight) = find_heaviest_node(&graph, None);
+        let (node_heaviest, node_weight) =
+            find_heaviest_node(&graph, None, None, &NodeScore::default());
         assert_eq!(
             graph.node_weight(node_heaviest).unwrap(),
             "NC_046966.1:31878"
@@ -408,7 +2954,8 @@ mod tests {
         assert_eq!(round(node_weight, 4), f32::INFINITY);
         // Round #4
         graph.remove_node(graph_idx["NC_046966.1:31878"]);
-        let (node_heaviest, node_weight) = find_heaviest_node(&graph, None);
+        let (node_heaviest, node_weight) =
+            find_heaviest_node(&graph, None, None, &NodeScore::default());
         assert_eq!(
             graph.node_weight(node_heaviest).unwrap(),
             "NC_046966.1:42518"
@@ -416,7 +2963,8 @@ mod tests {
         assert_eq!(round(node_weight, 4), f32::INFINITY);
         // Round #5
         graph.remove_node(graph_idx["NC_046966.1:42518"]);
-        let (node_heaviest, node_weight) = find_heaviest_node(&graph, None);
+        let (node_heaviest, node_weight) =
+            find_heaviest_node(&graph, None, None, &NodeScore::default());
         assert_eq!(
             graph.node_weight(node_heaviest).unwrap(),
             "NC_046966.1:45910"
@@ -424,7 +2972,8 @@ mod tests {
         assert_eq!(round(node_weight, 4), f32::INFINITY);
         // Round #6
         graph.remove_node(graph_idx["NC_046966.1:45910"]);
-        let (node_heaviest, node_weight) = find_heaviest_node(&graph, None);
+        let (node_heaviest, node_weight) =
+            find_heaviest_node(&graph, None, None, &NodeScore::default());
         assert_eq!(
             graph.node_weight(node_heaviest).unwrap(),
             "NC_046966.1:38024"
@@ -432,16 +2981,200 @@ mod tests {
         assert_eq!(round(node_weight, 4), 8.2862);
     }
 
+    #[test]
+    fn test_graph_prune() {
+        let (mut graph, graph_idx, _edges_sweep, _meta) = graph_read(
+            BufReader::new(File::open("test/example.tsv").expect("cannot open input file")),
+            &ReadOpts {
+                has_header: true,
+                weight_field: "r2".to_string(),
+                weight_filter: Some("r2 > 0.2".to_string()),
+                ..Default::default()
+            },
+        );
+        let mut graph_keep = graph.clone();
+        let nodes_excl = graph_prune(
+            &mut graph,
+            1,
+            false,
+            &HashSet::new(),
+            None,
+            &NodeScore::default(),
+        );
+        assert_eq!(graph.edge_count(), 0);
+        assert_eq!(graph.node_count() + nodes_excl.len(), 65);
+        assert!(nodes_excl.contains(&"NC_046966.1:10729".to_string()));
+
+        // Heaviest node is kept, so all its neighbors are excluded
+        let nodes_keep = HashSet::from([graph_idx["NC_046966.1:10729"]]);
+        let neighbors: Vec<String> = graph_keep
+            .neighbors(graph_idx["NC_046966.1:10729"])
+            .map(|node| graph_keep[node].clone())
+            .collect();
+        let nodes_excl = graph_prune(
+            &mut graph_keep,
+            1,
+            false,
+            &nodes_keep,
+            None,
+            &NodeScore::default(),
+        );
+        assert_eq!(graph_keep.edge_count(), 0);
+        assert!(graph_keep.contains_node(graph_idx["NC_046966.1:10729"]));
+        assert!(!neighbors.is_empty());
+        assert!(neighbors.iter().all(|node| nodes_excl.contains(node)));
+    }
+
+    #[test]
+    fn test_graph_prune_nested() {
+        let (graph, _graph_idx, edges_sweep, _meta) = graph_read(
+            BufReader::new(File::open("test/example.tsv").expect("cannot open input file")),
+            &ReadOpts {
+                has_header: true,
+                weight_field: "r2".to_string(),
+                sweep_filters: vec!["r2 > 0.5".to_string(), "r2 > 0.2".to_string()],
+                ..Default::default()
+            },
+        );
+        let order = sweep_order(&edges_sweep, 2);
+        assert_eq!(order, vec![1, 0]);
+
+        // Each sweep keeps the nodes kept on the previous (stricter) one
+        let mut sweep_kept = vec![HashSet::new(); 2];
+        let mut nodes_keep = HashSet::new();
+        for sweep_idx in &order {
+            let mut graph_sweep = graph.clone();
+            super::graph_sweep(&mut graph_sweep, &edges_sweep, *sweep_idx);
+            graph_prune(
+                &mut graph_sweep,
+                1,
+                false,
+                &nodes_keep,
+                None,
+                &NodeScore::default(),
+            );
+            assert_eq!(graph_sweep.edge_count(), 0);
+            nodes_keep = graph_sweep.node_indices().collect();
+            sweep_kept[*sweep_idx] = nodes_keep.clone();
+        }
+        assert!(sweep_kept[1].is_subset(&sweep_kept[0]));
+        assert!(sweep_kept[1].len() < sweep_kept[0].len());
+
+        // Nodes kept on the strictest sweep map to it, and nodes only kept on the loosest one to it
+        let nodes_sweep = sweep_strictest(&sweep_kept, &order);
+        assert_eq!(nodes_sweep.len(), sweep_kept[0].len());
+        assert!(sweep_kept[1].iter().all(|node| nodes_sweep[node] == 1));
+        assert!(sweep_kept[0]
+            .difference(&sweep_kept[1])
+            .all(|node| nodes_sweep[node] == 0));
+        assert_eq!(
+            nodes_sweep.values().collect::<HashSet<&usize>>(),
+            HashSet::from([&0, &1])
+        );
+        assert!(graph
+            .node_indices()
+            .filter(|node| !sweep_kept[0].contains(node))
+            .all(|node| !nodes_sweep.contains_key(&node)));
+    }
+
+    #[test]
+    fn test_find_heaviest_node_seed() {
+        let (graph, _graph_idx, _edges_sweep, _meta) = graph_read(
+            BufReader::new(File::open("test/example.tsv").expect("cannot open input file")),
+            &ReadOpts {
+                has_header: true,
+                weight_field: "r2".to_string(),
+                weight_filter: Some("r2 > 0.2".to_string()),
+                ..Default::default()
+            },
+        );
+        let node_score = NodeScore::default();
+
+        // Several nodes are tied (infinite weight), so alphabetical order picks the first
+        let (node_alpha, weight_alpha) = find_heaviest_node(&graph, None, None, &node_score);
+        assert_eq!(graph[node_alpha], "NC_046966.1:10729");
+        assert_eq!(weight_alpha, f32::INFINITY);
+
+        // A seed breaks the tie differently, but with the same weight
+        let (node_seed, weight_seed) = find_heaviest_node(&graph, None, Some(123), &node_score);
+        assert_ne!(node_seed, node_alpha);
+        assert_eq!(weight_seed, weight_alpha);
+
+        // Same seed is reproducible, and different seeds pick different nodes
+        assert_eq!(
+            find_heaviest_node(&graph, None, Some(123), &node_score).0,
+            node_seed
+        );
+        let nodes_seed: HashSet<NodeIndex<GraphIdx>> = (0..20)
+            .map(|seed| find_heaviest_node(&graph, None, Some(seed), &node_score).0)
+            .collect();
+        assert!(nodes_seed.len() > 1);
+    }
+
+    #[test]
+    fn test_graph_jitter() {
+        let (graph, _graph_idx, _edges_sweep, _meta) = graph_read(
+            BufReader::new(File::open("test/example.tsv").expect("cannot open input file")),
+            &ReadOpts {
+                has_header: true,
+                weight_field: "r2".to_string(),
+                weight_filter: Some("r2 > 0.2".to_string()),
+                ..Default::default()
+            },
+        );
+        let mut graph_jittered = graph.clone();
+        graph_jitter(&mut graph_jittered, 0.001, 123);
+        assert!(graph
+            .edge_weights()
+            .zip(graph_jittered.edge_weights())
+            .filter(|(w, _)| w.is_finite())
+            .all(|(w, w_jittered)| (w - w_jittered).abs() <= 0.001 + f32::EPSILON));
+        assert!(graph
+            .edge_weights()
+            .zip(graph_jittered.edge_weights())
+            .any(|(w, w_jittered)| w != w_jittered));
+    }
+
+    #[test]
+    fn test_sweep_order() {
+        assert_eq!(sweep_order(&[0b011, 0b010, 0b111], 3), vec![1, 0, 2]);
+    }
+
+    #[test]
+    fn test_graph_sweep() {
+        let (graph, _graph_idx, edges_sweep, _meta) = graph_read(
+            BufReader::new(File::open("test/example.tsv").expect("cannot open input file")),
+            &ReadOpts {
+                has_header: true,
+                weight_field: "r2".to_string(),
+                sweep_filters: vec!["r2 > 0.5".to_string(), "r2 > 0.2".to_string()],
+                ..Default::default()
+            },
+        );
+        assert_eq!(edges_sweep.len(), graph.edge_count());
+        assert_eq!(graph.edge_count(), 104);
+
+        let mut graph_loose = graph.clone();
+        graph_sweep(&mut graph_loose, &edges_sweep, 1);
+        assert_eq!(graph_loose.edge_count(), 104);
+
+        let mut graph_strict = graph.clone();
+        graph_sweep(&mut graph_strict, &edges_sweep, 0);
+        assert!(graph_strict.edge_count() < 104);
+        assert!(graph_strict.edge_weights().all(|weight| *weight > 0.5));
+    }
+
     #[test]
     fn test_find_connected_components() {
         use petgraph::algo::{kosaraju_scc, tarjan_scc};
-        let (graph, _graph_idx) = graph_read(
+        let (graph, _graph_idx, _edges_sweep, _meta) = graph_read(
             BufReader::new(File::open("test/example.tsv").expect("cannot open input file")),
-            true,
-            "r2".to_string(),
-            Some("r2 > 0.2".to_string()),
-            false,
-            4,
+            &ReadOpts {
+                has_header: true,
+                weight_field: "r2".to_string(),
+                weight_filter: Some("r2 > 0.2".to_string()),
+                ..Default::default()
+            },
         );
         let ccs = tarjan_scc(&graph);
         assert_eq!(ccs.len(), 9);
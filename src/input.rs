@@ -0,0 +1,655 @@
+use crate::columnar::columnar_open;
+use crate::graph::{Delimiter, HeaderLine, HeaderMode, InputFormat, ReadOpts};
+use crate::matrix::{MatrixReader, MtxReader};
+use flate2::read::{GzDecoder, MultiGzDecoder};
+use rayon::prelude::*;
+use std::{
+    fs::File,
+    io::{stdin, BufRead, BufReader, Cursor, Read},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+use tracing::{debug, error, info};
+
+/// Compression of an input stream.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Compression {
+    None,
+    Gzip,
+    /// Blocked gzip (e.g. from 'bgzip'), a multi-member gzip with block sizes in the header
+    Bgzf,
+    Zstd,
+    Bzip2,
+    Xz,
+}
+
+impl Compression {
+    /// Detect compression from the first bytes of a stream.
+    pub fn detect(magic: &[u8]) -> Self {
+        match magic {
+            [0x1f, 0x8b, _, flags, _, _, _, _, _, _, _, _, b'B', b'C', ..] if flags & 0x04 != 0 => {
+                Self::Bgzf
+            }
+            [0x1f, 0x8b, ..] => Self::Gzip,
+            [0x28, 0xb5, 0x2f, 0xfd, ..] => Self::Zstd,
+            [b'B', b'Z', b'h', ..] => Self::Bzip2,
+            [0xfd, b'7', b'z', b'X', b'Z', 0x00, ..] => Self::Xz,
+            _ => Self::None,
+        }
+    }
+}
+
+/// Reader of BGZF streams, decompressing blocks in parallel (on the rayon thread pool).
+pub struct BgzfReader<R> {
+    inner: R,
+    buf: Vec<u8>,
+    pos: usize,
+}
+
+impl<R: Read> BgzfReader<R> {
+    pub fn new(inner: R) -> Self {
+        Self {
+            inner,
+            buf: Vec::new(),
+            pos: 0,
+        }
+    }
+
+    /// Read next (compressed) block, or `None` at the end of the stream.
+    fn read_block(&mut self) -> std::io::Result<Option<Vec<u8>>> {
+        // Fixed header (up to XLEN)
+        let mut block = Vec::with_capacity(64 * 1024);
+        (&mut self.inner).take(12).read_to_end(&mut block)?;
+        if block.is_empty() {
+            return Ok(None);
+        }
+        if block.len() < 12 || block[..2] != [0x1f, 0x8b] || block[3] & 0x04 == 0 {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                "invalid BGZF block header",
+            ));
+        }
+
+        // Extra subfields, with block size ('BC')
+        let xlen = u16::from_le_bytes([block[10], block[11]]) as usize;
+        (&mut self.inner)
+            .take(xlen as u64)
+            .read_to_end(&mut block)?;
+        let mut bsize = None;
+        let mut i = 12;
+        while i + 4 <= block.len() {
+            let slen = u16::from_le_bytes([block[i + 2], block[i + 3]]) as usize;
+            if block[i..i + 2] == *b"BC" && slen == 2 && i + 6 <= block.len() {
+                bsize = Some(u16::from_le_bytes([block[i + 4], block[i + 5]]) as usize + 1);
+            }
+            i += 4 + slen;
+        }
+        let Some(bsize) = bsize.filter(|bsize| *bsize >= block.len()) else {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                "invalid BGZF block size",
+            ));
+        };
+
+        // Rest of block (compressed data and footer)
+        let n = block.len();
+        block.resize(bsize, 0);
+        self.inner.read_exact(&mut block[n..])?;
+        Ok(Some(block))
+    }
+
+    /// Decompress next batch of blocks (in parallel) into the buffer, returning the number of blocks read.
+    fn fill(&mut self) -> std::io::Result<usize> {
+        let n_blocks = 16 * rayon::current_num_threads();
+        let mut blocks = Vec::with_capacity(n_blocks);
+        while blocks.len() < n_blocks {
+            match self.read_block()? {
+                Some(block) => blocks.push(block),
+                None => break,
+            }
+        }
+
+        let n_blocks = blocks.len();
+        let blocks: Vec<Vec<u8>> = blocks
+            .par_iter()
+            .map(|block| {
+                let mut data = Vec::with_capacity(64 * 1024);
+                GzDecoder::new(&block[..]).read_to_end(&mut data)?;
+                Ok(data)
+            })
+            .collect::<std::io::Result<_>>()?;
+        self.buf = blocks.concat();
+        self.pos = 0;
+        Ok(n_blocks)
+    }
+}
+
+impl<R: Read> Read for BgzfReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        // Skip empty blocks (e.g. EOF marker) until data or end of stream
+        while self.pos == self.buf.len() {
+            if self.fill()? == 0 {
+                return Ok(0);
+            }
+        }
+        let n = buf.len().min(self.buf.len() - self.pos);
+        buf[..n].copy_from_slice(&self.buf[self.pos..self.pos + n]);
+        self.pos += n;
+        Ok(n)
+    }
+}
+
+/// Open input file (or STDIN, if no path), decompressing it if needed.
+///
+/// Compression is detected from the magic bytes (not the file extension), and concatenated streams (e.g. multi-member gzip) are read completely.
+pub fn input_open(path: Option<&Path>) -> Box<dyn BufRead> {
+    let mut reader: Box<dyn Read> = match path {
+        Some(path) => {
+            info!("Reading input file {:?}", path);
+            Box::new(File::open(path).expect("cannot open input file"))
+        }
+        None => {
+            info!("Reading from STDIN");
+            Box::new(stdin().lock())
+        }
+    };
+
+    // Peek at the first bytes (and put them back)
+    let mut magic = Vec::new();
+    reader
+        .by_ref()
+        .take(16)
+        .read_to_end(&mut magic)
+        .expect("cannot read from input");
+    let compression = Compression::detect(&magic);
+    let reader = Cursor::new(magic).chain(reader);
+
+    info!("Input compression: {compression:?}");
+    match compression {
+        Compression::None => Box::new(BufReader::with_capacity(128 * 1024, reader)),
+        Compression::Gzip => Box::new(BufReader::with_capacity(
+            128 * 1024,
+            MultiGzDecoder::new(reader),
+        )),
+        Compression::Bgzf => Box::new(BufReader::with_capacity(
+            128 * 1024,
+            BgzfReader::new(reader),
+        )),
+        Compression::Zstd => Box::new(BufReader::with_capacity(
+            128 * 1024,
+            zstd::Decoder::new(reader).expect("cannot initialize zstd decoder"),
+        )),
+        Compression::Bzip2 => Box::new(BufReader::with_capacity(
+            128 * 1024,
+            bzip2::read::MultiBzDecoder::new(reader),
+        )),
+        Compression::Xz => Box::new(BufReader::with_capacity(
+            128 * 1024,
+            xz2::read::XzDecoder::new_multi_decoder(reader),
+        )),
+    }
+}
+
+/// Expand glob patterns (e.g. "ld/chr*.tsv.gz") into input files, in alphabetical order.
+///
+/// Paths without wildcards are kept as they are, but all files must exist (so that errors are reported before reading any of them).
+pub fn input_paths(patterns: &[PathBuf]) -> Vec<PathBuf> {
+    let mut paths = Vec::new();
+    for pattern in patterns {
+        let pattern_str = pattern.to_string_lossy();
+        if !pattern_str.contains(['*', '?', '[']) {
+            if !pattern.exists() {
+                error!("input file {:?} does not exist", pattern);
+                std::process::exit(-1);
+            }
+            paths.push(pattern.clone());
+            continue;
+        }
+        let mut matches: Vec<PathBuf> = glob::glob(&pattern_str)
+            .unwrap_or_else(|e| {
+                error!("invalid input pattern '{pattern_str}': {e}");
+                std::process::exit(-1);
+            })
+            .map(|path| path.expect("cannot read input path"))
+            .collect();
+        if matches.is_empty() {
+            error!("no input files match pattern '{pattern_str}'");
+            std::process::exit(-1);
+        }
+        matches.sort();
+        paths.append(&mut matches);
+    }
+    paths
+}
+
+/// Input files read one after the other (see `MultiReader`), with the line of the combined input at which each starts.
+///
+/// It is shared with the reader (and filled in while reading), so that edges can be reported by input file.
+#[derive(Clone, Default)]
+pub struct InputFiles(Arc<Mutex<Vec<(usize, PathBuf)>>>);
+
+impl InputFiles {
+    fn push(&self, line: usize, path: PathBuf) {
+        self.0.lock().unwrap().push((line, path));
+    }
+
+    /// Input files opened so far, with their first line in the combined input.
+    pub fn starts(&self) -> Vec<(usize, PathBuf)> {
+        self.0.lock().unwrap().clone()
+    }
+}
+
+/// Location of an input line (for messages): its line number and, if several inputs are read, its input file.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct LineLoc<'a> {
+    /// Line number (within its input file)
+    pub line: usize,
+    /// Index (in `InputFiles::starts`) and path of the input file
+    pub file: Option<(usize, &'a Path)>,
+}
+
+impl<'a> LineLoc<'a> {
+    /// Location of line `n_line` of the combined input, given the input files (see `InputFiles::starts`).
+    pub fn new(starts: &'a [(usize, PathBuf)], n_line: usize) -> Self {
+        match starts
+            .partition_point(|(start, _)| *start <= n_line)
+            .checked_sub(1)
+        {
+            Some(i) => Self {
+                line: n_line - starts[i].0 + 1,
+                file: Some((i, starts[i].1.as_path())),
+            },
+            None => Self {
+                line: n_line,
+                file: None,
+            },
+        }
+    }
+}
+
+impl std::fmt::Display for LineLoc<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self.file {
+            Some((_, path)) => write!(f, "line {0} of {1:?}", self.line, path),
+            None => write!(f, "line {}", self.line),
+        }
+    }
+}
+
+/// Reader of several inputs, one after the other.
+///
+/// If inputs have a header (given or detected, see `HeaderMode`), it must be the same on all of them and is only kept from the first one; other lines (including comments and empty lines, to be skipped by the reader) are kept, so that line numbers within each input can be recovered (see `InputFiles` and `LineLoc`).
+pub struct MultiReader {
+    paths: std::vec::IntoIter<PathBuf>,
+    open: InputOpen,
+    header_mode: HeaderMode,
+    delimiter: Delimiter,
+    /// Header of the first input (`None` if it has no header)
+    header: Option<Option<Vec<String>>>,
+    reader: Option<(PathBuf, Box<dyn BufRead>)>,
+    /// Last leading comment of the current input (without the '#'), which can be its header
+    comment: Option<String>,
+    /// Whether the header of the current input was already read
+    header_read: bool,
+    /// Input files (with their first line in the combined input)
+    files: InputFiles,
+    /// Number of lines of the combined input read so far
+    n_lines: usize,
+    line: Vec<u8>,
+    pos: usize,
+}
+
+impl MultiReader {
+    pub fn new(
+        paths: Vec<PathBuf>,
+        open: InputOpen,
+        header_mode: HeaderMode,
+        delimiter: Delimiter,
+        files: InputFiles,
+    ) -> Self {
+        Self {
+            paths: paths.into_iter(),
+            open,
+            header_mode,
+            delimiter,
+            header: None,
+            reader: None,
+            comment: None,
+            header_read: false,
+            files,
+            n_lines: 0,
+            line: Vec::new(),
+            pos: 0,
+        }
+    }
+
+    /// Read next line, or an empty line at the end of all inputs.
+    fn next_line(&mut self) -> std::io::Result<()> {
+        loop {
+            let Some((path, reader)) = &mut self.reader else {
+                match self.paths.next() {
+                    Some(path) => {
+                        self.reader = Some((path.clone(), (self.open)(&path)));
+                        self.files.push(self.n_lines + 1, path);
+                        self.comment = None;
+                        self.header_read = false;
+                        continue;
+                    }
+                    None => {
+                        self.line.clear();
+                        return Ok(());
+                    }
+                }
+            };
+
+            self.line.clear();
+            self.pos = 0;
+            if reader.read_until(b'\n', &mut self.line)? == 0 {
+                debug!("Finished reading {:?}", path);
+                self.reader = None;
+                continue;
+            }
+            if self.line.last() != Some(&b'\n') {
+                self.line.push(b'\n');
+            }
+            self.n_lines += 1;
+
+            // Comments and empty lines are kept (so that lines keep their number within each input)
+            if self.line.starts_with(b"#") {
+                if !self.header_read {
+                    self.comment = Some(String::from_utf8_lossy(&self.line[1..]).to_string())
+                        .filter(|comment| !comment.starts_with('#'));
+                }
+                return Ok(());
+            }
+            if self.header_read || self.line.iter().all(u8::is_ascii_whitespace) {
+                return Ok(());
+            }
+
+            // Check header (on the first line or leading comment)
+            self.header_read = true;
+            let line = String::from_utf8_lossy(&self.line).to_string();
+            let line = line.trim_end_matches(['\n', '\r']);
+            let comment = self
+                .comment
+                .as_deref()
+                .map(|comment| comment.trim_end_matches(['\n', '\r']));
+            let (header_line, reason) = self.header_mode.resolve(comment, line, self.delimiter);
+            debug!("Input header of {:?}: {reason}", path);
+            let header_str = match header_line {
+                HeaderLine::Comment => comment,
+                HeaderLine::First => Some(line),
+                HeaderLine::None => None,
+            };
+            if self.delimiter == Delimiter::Auto {
+                self.delimiter = Delimiter::detect(header_str.unwrap_or(line));
+            }
+            let header = header_str.map(|header| self.delimiter.split(header));
+            match &self.header {
+                None => self.header = Some(header),
+                Some(first_header) if *first_header != header => {
+                    error!(
+                        "header of input file {:?} ({:?}) differs from the first input file ({:?})",
+                        path, header, first_header
+                    );
+                    std::process::exit(-1);
+                }
+                // Header line of other inputs is replaced by an empty line
+                Some(_) if header_line == HeaderLine::First => {
+                    self.line.clear();
+                    self.line.push(b'\n');
+                }
+                Some(_) => {}
+            }
+            return Ok(());
+        }
+    }
+}
+
+impl Read for MultiReader {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let data = self.fill_buf()?;
+        let n = buf.len().min(data.len());
+        buf[..n].copy_from_slice(&data[..n]);
+        self.consume(n);
+        Ok(n)
+    }
+}
+
+impl BufRead for MultiReader {
+    fn fill_buf(&mut self) -> std::io::Result<&[u8]> {
+        if self.pos == self.line.len() {
+            self.next_line()?;
+        }
+        Ok(&self.line[self.pos..])
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.pos += amt;
+    }
+}
+
+/// Open input file (or STDIN, if no path) in the given format, as delimited text.
+pub fn input_open_format(
+    path: Option<&Path>,
+    format: InputFormat,
+    opts: &ReadOpts,
+) -> Box<dyn BufRead> {
+    match (path, format) {
+        (Some(path), InputFormat::Arrow | InputFormat::Parquet) => Box::new(columnar_open(
+            path,
+            format,
+            &opts.columns(),
+            opts.node_fields.is_none(),
+        )),
+        (None, InputFormat::Arrow | InputFormat::Parquet) => {
+            error!("{format:?} input cannot be read from STDIN");
+            std::process::exit(-1);
+        }
+        (_, InputFormat::Matrix | InputFormat::Mtx) => {
+            let (sites, _sites_pos) = crate::graph::nodes_read(
+                opts.matrix_sites
+                    .as_deref()
+                    .expect("LD matrix input requires a sites file"),
+            );
+            info!("Read {} matrix site(s)", sites.len());
+            if format == InputFormat::Mtx {
+                Box::new(MtxReader::new(input_open(path), sites))
+            } else {
+                Box::new(MatrixReader::new(input_open(path), sites, opts.delimiter))
+            }
+        }
+        _ => input_open(path),
+    }
+}
+
+/// Function to open each input file.
+pub type InputOpen = Box<dyn Fn(&Path) -> Box<dyn BufRead>>;
+
+/// Open input files (or STDIN, if none) in the given format, to be read one after the other.
+pub fn inputs_open(paths: Vec<PathBuf>, format: InputFormat, opts: &ReadOpts) -> Box<dyn BufRead> {
+    if paths.len() > 1 && matches!(format, InputFormat::Matrix | InputFormat::Mtx) {
+        error!("LD matrix input must be a single file (or one per layer, with '--layers')");
+        std::process::exit(-1);
+    }
+    if paths.len() > 1 {
+        info!("Reading {} input files", paths.len());
+        let columns = opts.columns();
+        let columns_first = opts.node_fields.is_none();
+        let open: InputOpen = match format {
+            InputFormat::Arrow | InputFormat::Parquet => {
+                Box::new(move |path| Box::new(columnar_open(path, format, &columns, columns_first)))
+            }
+            _ => Box::new(|path| input_open(Some(path))),
+        };
+        Box::new(MultiReader::new(
+            paths,
+            open,
+            opts.header_mode(),
+            opts.delimiter,
+            opts.input_files.clone(),
+        ))
+    } else {
+        input_open_format(paths.first().map(PathBuf::as_path), format, opts)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    #[test]
+    fn test_compression_detect() {
+        assert_eq!(
+            Compression::detect(b"site1\tsite2\tr2\n"),
+            Compression::None
+        );
+        assert_eq!(Compression::detect(&[0x1f, 0x8b, 8, 0]), Compression::Gzip);
+        assert_eq!(
+            Compression::detect(&[0x1f, 0x8b, 8, 4, 0, 0, 0, 0, 0, 0xff, 6, 0, b'B', b'C', 2, 0]),
+            Compression::Bgzf
+        );
+        assert_eq!(
+            Compression::detect(&[0x28, 0xb5, 0x2f, 0xfd]),
+            Compression::Zstd
+        );
+        assert_eq!(Compression::detect(b"BZh91AY"), Compression::Bzip2);
+        assert_eq!(Compression::detect(b"\xfd7zXZ\x00"), Compression::Xz);
+        assert_eq!(Compression::detect(b""), Compression::None);
+    }
+
+    #[test]
+    fn test_input_open_gzip() {
+        let expected = std::fs::read_to_string("test/example.tsv").unwrap();
+        let mut decoded = String::new();
+        input_open(Some(Path::new("test/example.tsv.gz")))
+            .read_to_string(&mut decoded)
+            .unwrap();
+        assert_eq!(decoded, expected);
+
+        // Multi-member gzip (e.g. concatenated files) is read completely
+        let (head, tail) = expected.split_at(expected.len() / 2);
+        let mut data = Vec::new();
+        for part in [head, tail] {
+            let mut encoder =
+                flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+            encoder.write_all(part.as_bytes()).unwrap();
+            data.extend(encoder.finish().unwrap());
+        }
+        let path = std::env::temp_dir().join(format!("prune_graph_test_{}.gz", std::process::id()));
+        std::fs::write(&path, data).unwrap();
+        let mut decoded = String::new();
+        input_open(Some(&path))
+            .read_to_string(&mut decoded)
+            .unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(decoded, expected);
+    }
+
+    #[test]
+    fn test_bgzf_reader() {
+        let expected = std::fs::read_to_string("test/example.tsv").unwrap();
+
+        // Small BGZF blocks (more than one batch per thread), and the empty EOF block
+        let mut data = Vec::new();
+        for chunk in expected.as_bytes().chunks(512) {
+            let mut encoder = flate2::GzBuilder::new()
+                .extra(vec![b'B', b'C', 2, 0, 0, 0])
+                .write(Vec::new(), flate2::Compression::default());
+            encoder.write_all(chunk).unwrap();
+            let mut block = encoder.finish().unwrap();
+            let bsize = (block.len() - 1) as u16;
+            block[16..18].copy_from_slice(&bsize.to_le_bytes());
+            data.extend(block);
+        }
+        data.extend([
+            0x1f, 0x8b, 8, 4, 0, 0, 0, 0, 0, 0xff, 6, 0, b'B', b'C', 2, 0, 0x1b, 0, 3, 0, 0, 0, 0,
+            0, 0, 0, 0, 0,
+        ]);
+        assert_eq!(Compression::detect(&data), Compression::Bgzf);
+
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(4)
+            .build()
+            .unwrap();
+        let mut decoded = String::new();
+        pool.install(|| {
+            BgzfReader::new(data.as_slice())
+                .read_to_string(&mut decoded)
+                .unwrap()
+        });
+        assert!(expected.len() / 512 > 16 * 4);
+        assert_eq!(decoded, expected);
+    }
+
+    #[test]
+    fn test_multi_reader() {
+        let paths = input_paths(&[PathBuf::from("test/example.tsv*")]);
+        assert_eq!(
+            paths,
+            vec![
+                PathBuf::from("test/example.tsv"),
+                PathBuf::from("test/example.tsv.gz")
+            ]
+        );
+
+        // Header is only kept from the first file (and replaced by an empty line on others)
+        let lines: Vec<String> = MultiReader::new(
+            paths,
+            Box::new(|path| input_open(Some(path))),
+            HeaderMode::Present,
+            Delimiter::Auto,
+            InputFiles::default(),
+        )
+        .lines()
+        .map(|line| line.unwrap())
+        .collect();
+        assert_eq!(lines.len(), 2002);
+        assert!(lines[0].starts_with("site1"));
+        assert_eq!(lines[1001], "");
+        assert_eq!(lines[1], lines[1002]);
+
+        // Header detected (also if prefixed by '#')
+        let opts = ReadOpts {
+            weight_field: "r2".to_string(),
+            ..Default::default()
+        };
+        let files = InputFiles::default();
+        let lines: Vec<String> = MultiReader::new(
+            vec![PathBuf::from("a"), PathBuf::from("b")],
+            Box::new(|path| {
+                Box::new(Cursor::new(match path.to_str() {
+                    Some("a") => "##source=test\n#site1\tsite2\tr2\nA\tB\t0.5\n",
+                    _ => "site1\tsite2\tr2\nB\tC\t0.3\n",
+                }))
+            }),
+            opts.header_mode(),
+            Delimiter::Auto,
+            files.clone(),
+        )
+        .lines()
+        .map(|line| line.unwrap())
+        .collect();
+        assert_eq!(
+            lines,
+            vec![
+                "##source=test",
+                "#site1\tsite2\tr2",
+                "A\tB\t0.5",
+                "",
+                "B\tC\t0.3"
+            ]
+        );
+
+        // Input file of each line
+        let starts = files.starts();
+        assert_eq!(
+            starts,
+            vec![(1, PathBuf::from("a")), (4, PathBuf::from("b"))]
+        );
+        assert_eq!(LineLoc::new(&starts, 3).to_string(), "line 3 of \"a\"");
+        assert_eq!(LineLoc::new(&starts, 5).to_string(), "line 2 of \"b\"");
+        assert_eq!(LineLoc::new(&[], 6).to_string(), "line 6");
+    }
+}
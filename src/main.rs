@@ -1,21 +1,24 @@
-use flate2::read;
-use std::ffi::OsStr;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
 use clap::Parser;
-use indicatif::ProgressStyle;
-use itertools::sorted;
-use petgraph::{algo::kosaraju_scc, dot::Dot};
+use graph::GraphIdx;
+use itertools::{sorted, Itertools};
+use petgraph::{algo::kosaraju_scc, dot::Dot, graph::NodeIndex};
 use rayon::{prelude::*, ThreadPoolBuilder};
 use std::{
+    collections::{HashMap, HashSet},
     fs::File,
-    io::{stdin, stdout, BufReader, Write},
+    io::{stdout, Write},
     time::Instant,
 };
-use tracing::{debug, enabled, error, info, info_span, trace, warn, Level};
+use tracing::{error, info, warn};
+mod cache;
+mod columnar;
+mod expr;
 mod graph;
+mod input;
+mod matrix;
 mod parse_args;
-use tracing_indicatif::span_ext::IndicatifSpanExt;
 use tracing_indicatif::IndicatifLayer;
 use tracing_subscriber::filter::LevelFilter;
 use tracing_subscriber::{fmt, prelude::*};
@@ -68,49 +71,228 @@ fn main() {
         .build_global()
         .expect("cannot create threadpool");
 
-    // Read TSV into graph
-    let (mut graph, _graph_idx) = if args.input.is_some() {
-        let fh = File::open(args.input.as_ref().unwrap()).expect("cannot open input file");
-        if Path::new(&args.input.as_ref().unwrap()).extension() == Some(OsStr::new("gz")) {
-            info!("Reading input Gzip file {:?}", &args.input.unwrap());
-            let reader_file_gz = BufReader::with_capacity(128 * 1024, read::GzDecoder::new(fh));
-            crate::graph::graph_read(
-                reader_file_gz,
-                args.header,
-                args.weight_field,
-                args.weight_filter,
-                args.weight_n_edges,
-                args.weight_precision,
-            )
+    // Input files
+    let inputs = crate::input::input_paths(&args.input);
+
+    // Input preset and format (explicit options take precedence)
+    let preset = args.preset.map(|preset| preset.opts());
+    if let Some(preset) = args.preset {
+        info!("Using {preset:?} preset");
+    }
+    let format = match (args.format, preset) {
+        (crate::graph::InputFormat::Auto, Some(preset)) => preset.format,
+        (crate::graph::InputFormat::Auto, None) => {
+            crate::graph::InputFormat::detect(inputs.first().map(PathBuf::as_path))
+        }
+        (format, _) => format,
+    };
+    info!("Input format: {format:?}");
+    if matches!(
+        format,
+        crate::graph::InputFormat::Matrix | crate::graph::InputFormat::Mtx
+    ) && args.matrix_sites.is_none()
+    {
+        error!("LD matrix input requires the sites of its rows/columns (see '--matrix-sites')");
+        std::process::exit(1);
+    }
+    let weight_field = args.weight_field.unwrap_or_else(|| {
+        preset
+            .map_or(format.weight_field(), |preset| preset.weight_field)
+            .to_string()
+    });
+
+    // Define sweep filters (numeric values are thresholds on the weight field)
+    let sweep_filters: Vec<String> = args
+        .sweep
+        .iter()
+        .map(|sweep| match (sweep.parse::<f64>(), &args.weight_expr) {
+            (Ok(threshold), Some(weight_expr)) => format!("({weight_expr}) > {threshold}"),
+            (Ok(threshold), None) => format!("{weight_field} > {threshold}"),
+            (Err(_), _) => sweep.clone(),
+        })
+        .collect();
+    if sweep_filters.len() > 64 {
+        error!("at most 64 sweep thresholds/filters are supported");
+        std::process::exit(1);
+    }
+
+    let read_opts = format.read_opts(crate::graph::ReadOpts {
+        has_header: args.header || preset.is_some_and(|preset| preset.has_header),
+        column_names: args.columns,
+        delimiter: args.delimiter,
+        node_fields: args.node_fields.or_else(|| {
+            preset
+                .and_then(|preset| preset.node_fields)
+                .map(|fields| fields.map(str::to_string).to_vec())
+        }),
+        pos_fields: None,
+        matrix_sites: args.matrix_sites,
+        weight_field,
+        weight_expr: args.weight_expr,
+        weight_filter: args.weight_filter.or_else(|| {
+            preset
+                .and_then(|preset| preset.weight_filter)
+                .map(str::to_string)
+        }),
+        weight_precision: args.weight_precision,
+        sweep_filters,
+        node_attrs: args.node_attrs.map(crate::graph::node_attrs_read),
+        missing_values: args.missing_values,
+        missing: args.missing,
+        invalid: args.invalid,
+        max_warnings: args.max_warnings,
+        duplicates: args.duplicates,
+        self_loops: args.self_loops,
+        negative: args.negative,
+        non_finite: args.non_finite,
+        non_finite_cap: args.non_finite_cap,
+        input_files: Default::default(),
+    });
+
+    // Define node score (number of edges is a preset)
+    let node_score = crate::graph::NodeScore::new(
+        if args.weight_n_edges {
+            "count"
         } else {
-            info!("Reading input file {:?}", &args.input.unwrap());
-            let reader_file = BufReader::new(fh);
-            crate::graph::graph_read(
-                reader_file,
-                args.header,
-                args.weight_field,
-                args.weight_filter,
-                args.weight_n_edges,
-                args.weight_precision,
-            )
+            &args.node_score
+        },
+        read_opts.node_attrs.as_ref(),
+    )
+    .unwrap_or_else(|e| {
+        error!("{e}");
+        std::process::exit(1);
+    });
+
+    // Read TSV into graph
+    let (mut graph, edges_sweep, mut meta) = if let Some(load_graph) = &args.load_graph {
+        // Cached graph only has the edge weights, so it can only be filtered on them
+        let cache_opts = crate::graph::ReadOpts {
+            sweep_filters: Vec::new(),
+            ..read_opts.clone()
+        };
+        crate::graph::graph_filter_check(&cache_opts).unwrap_or_else(|e| {
+            error!("{e} (other input columns are not stored in the graph cache)");
+            std::process::exit(1);
+        });
+        let (mut graph, edges_sweep, meta, cache_info) = crate::cache::graph_load(load_graph);
+        info!(
+            "Graph cache was built with weight '{0}' and filter '{1}'",
+            cache_info.weight,
+            cache_info.weight_filter.as_deref().unwrap_or("none")
+        );
+        if !read_opts.sweep_filters.is_empty()
+            && read_opts.sweep_filters != cache_info.sweep_filters
+        {
+            error!(
+                "sweep filters {:?} differ from the ones the graph cache was built with {:?}",
+                read_opts.sweep_filters, cache_info.sweep_filters
+            );
+            std::process::exit(1);
+        }
+        if read_opts.sweep_filters.is_empty() && !cache_info.sweep_filters.is_empty() {
+            warn!("Graph cache was built with sweep filters, so it includes edges passing any of them");
+        }
+        if cache_opts.weight_filter.is_some() {
+            crate::graph::graph_filter(&mut graph, &cache_opts);
+        }
+        (graph, edges_sweep, meta)
+    } else if args.layers {
+        // Each input file is a layer
+        if args.layer_combine == crate::graph::LayerCombine::WeightedMean
+            && args.layer_sizes.len() != inputs.len()
+        {
+            error!(
+                "weighted mean requires the sample size of each of the {} layers (see '--layer-sizes')",
+                inputs.len()
+            );
+            std::process::exit(1);
+        }
+        crate::graph::graph_filter_check(&read_opts).unwrap_or_else(|e| {
+            error!("{e}");
+            std::process::exit(1);
+        });
+        info!("Reading {} layer(s)", inputs.len());
+        // Layers are read unfiltered, so that filters apply to the combined weight
+        let layer_opts = crate::graph::ReadOpts {
+            weight_filter: None,
+            sweep_filters: Vec::new(),
+            ..read_opts.clone()
+        };
+        let mut layers = Vec::new();
+        let mut meta = crate::graph::GraphMeta::default();
+        for (i, input) in inputs.iter().enumerate() {
+            let (layer, _layer_idx, _edges_sweep, layer_meta) = crate::graph::graph_read(
+                crate::input::input_open_format(Some(input), format, &layer_opts),
+                &layer_opts,
+            );
+            layers.push(layer);
+            if i == 0 {
+                meta.header = layer_meta.header;
+            } else if layer_meta.header != meta.header {
+                error!(
+                    "header of layer {:?} ({:?}) differs from the first layer ({:?})",
+                    input, layer_meta.header, meta.header
+                );
+                std::process::exit(1);
+            }
+            meta.nodes_pos.extend(layer_meta.nodes_pos);
         }
+        info!("Combining layers ({:?})", args.layer_combine);
+        let mut graph = crate::graph::graph_layers(
+            layers,
+            args.layer_combine,
+            &args.layer_sizes,
+            args.layer_missing,
+            read_opts.weight_precision,
+        );
+        let edges_sweep = crate::graph::graph_filter(&mut graph, &read_opts);
+        (graph, edges_sweep, meta)
     } else {
-        info!("Reading from STDIN");
-        let reader_stdin = stdin().lock();
-        crate::graph::graph_read(
-            reader_stdin,
-            args.header,
-            args.weight_field,
-            args.weight_filter,
-            args.weight_n_edges,
-            args.weight_precision,
-        )
+        let reader = crate::input::inputs_open(inputs, format, &read_opts);
+        let (graph, _graph_idx, edges_sweep, meta) = crate::graph::graph_read(reader, &read_opts);
+        (graph, edges_sweep, meta)
     };
 
+    // Node universe (by default, the sites of an LD matrix), so that nodes without any edges are kept
+    let nodes_universe = args.nodes.as_deref().or(match (&args.load_graph, format) {
+        (None, crate::graph::InputFormat::Matrix | crate::graph::InputFormat::Mtx) => {
+            read_opts.matrix_sites.as_deref()
+        }
+        _ => None,
+    });
+    if let Some(nodes_universe) = nodes_universe {
+        let (nodes, nodes_pos) = crate::graph::nodes_read(nodes_universe);
+        let (nodes_unknown, n_edges_unknown) = crate::graph::graph_nodes_unknown(&graph, &nodes);
+        if !nodes_unknown.is_empty() {
+            warn!(
+                "{} node(s) (in {n_edges_unknown} edge(s)) are not in the node universe, e.g.: {}",
+                nodes_unknown.len(),
+                nodes_unknown[..nodes_unknown.len().min(5)].join(", ")
+            );
+        }
+        let n_added = crate::graph::graph_add_nodes(&mut graph, nodes);
+        info!("Added {n_added} node(s) without any edges from node universe");
+        for (node, pos) in nodes_pos {
+            meta.nodes_pos.entry(node).or_insert(pos);
+        }
+    }
+
+    // Save graph to cache file
+    if let Some(save_graph) = &args.save_graph {
+        crate::cache::graph_save(
+            save_graph,
+            &graph,
+            &edges_sweep,
+            &meta,
+            &crate::cache::CacheInfo::new(&read_opts),
+        );
+    }
+    let sweep_filters = read_opts.sweep_filters;
+
     // Open subset file
-    if args.subset.is_some() {
+    if let Some(subset) = args.subset {
         info!("Subsetting nodes based on input file");
-        crate::graph::graph_subset(&mut graph, args.subset.expect("invalid subset option"));
+        crate::graph::graph_subset(&mut graph, subset);
     }
 
     if graph.node_count() == 0 {
@@ -126,11 +308,10 @@ fn main() {
     );
 
     // Saving components to file
-    if args.out_comps.is_some() {
+    if let Some(out_comps) = args.out_comps {
         let init_comps = kosaraju_scc(&graph);
         info!("Writing {} component(s) to JSONL file", init_comps.len());
-        let mut comps_file =
-            File::create(args.out_comps.unwrap()).expect("Cannot create components file!");
+        let mut comps_file = File::create(out_comps).expect("Cannot create components file!");
         for comp in init_comps.iter() {
             comps_file
                 .write_all(b"[\"")
@@ -156,17 +337,20 @@ fn main() {
     }
 
     // Print graph
-    if args.out_graph.is_some() {
+    if let Some(out_graph) = args.out_graph {
         info!("Saving graph as dot");
         if graph.node_count() > 10000 {
             warn!("Plotting graphs with more than 10000 nodes can be slow and not very informative")
         }
-        let mut out_graph = File::create(args.out_graph.unwrap()).expect("cannot open graph file!");
+        let mut out_graph = File::create(out_graph).expect("cannot open graph file!");
         let output = format!("{}", Dot::new(&graph));
         out_graph
             .write_all(output.as_bytes())
             .expect("cannot write to graph file!");
     }
+    if let Some(out_mtx) = args.out_mtx {
+        crate::matrix::graph_mtx_write(&graph, &out_mtx);
+    }
 
     if args.keep_heavy {
         info!(
@@ -177,91 +361,173 @@ fn main() {
         info!("Pruning heaviest position ({} threads)", args.n_threads);
     }
 
-    let mut n_iters = 0;
-    let mut delta_n_nodes = 0;
-    let mut delta_n_edges = graph.edge_count() as u64;
-
-    // Initialize progress bar
-    let prune_span = info_span!("prune");
-    prune_span.pb_set_length(delta_n_edges);
-    prune_span.pb_set_style(
-        &ProgressStyle::with_template(
-            "{bar:50} {pos:>10}/{len} edges pruned in {elapsed} ({per_sec:>0}) {msg}",
-        )
-        .unwrap(),
-    );
-    let prune_span_enter = prune_span.enter();
-
-    // Store deleted nodes
-    let mut nodes_excl = Vec::<String>::new();
-    while graph.edge_count() > 0 {
-        // Find heaviest nodes
-        let nodes_heavy = if args.mode == 1 {
-            kosaraju_scc(&graph)
-                .par_iter()
-                .filter(|x| x.len() > 1)
-                .map(|x| crate::graph::find_heaviest_node(&graph, Some(x)))
-                .collect()
-        } else {
-            vec![crate::graph::find_heaviest_node(&graph, None)]
-        };
-        trace!("{:?}", nodes_heavy);
-
-        // Process heaviest node
-        for (node_heavy, _node_heavy_weight) in &nodes_heavy {
-            if args.keep_heavy {
-                let mut nodes_del = graph.neighbors_undirected(*node_heavy).detach();
-                while let Some(node_neighb) = nodes_del.next_node(&graph) {
-                    nodes_excl.push(graph.node_weight(node_neighb).unwrap().to_string());
-                    graph.remove_node(node_neighb);
-                    delta_n_nodes += 1;
+    // Replicates (with random tie-breaking and jitter)
+    if let Some(n_replicates) = args.replicates {
+        info!("Running {n_replicates} replicate(s)");
+        let seed = args.seed.unwrap_or(0);
+        let reps_kept: Vec<Vec<NodeIndex<GraphIdx>>> = (0..n_replicates)
+            .into_par_iter()
+            .map(|rep| {
+                let rep_seed = seed.wrapping_add(rep as u64);
+                let mut graph_rep = graph.clone();
+                if args.jitter > 0.0 {
+                    crate::graph::graph_jitter(&mut graph_rep, args.jitter, rep_seed);
                 }
-            } else {
-                nodes_excl.push(graph.node_weight(*node_heavy).unwrap().to_string());
-                graph.remove_node(*node_heavy);
-                delta_n_nodes += 1;
-            }
+                crate::graph::graph_prune(
+                    &mut graph_rep,
+                    args.mode,
+                    args.keep_heavy,
+                    &HashSet::new(),
+                    Some(rep_seed),
+                    &node_score,
+                );
+                graph_rep.node_indices().collect()
+            })
+            .collect();
+        let mut nodes_freq = HashMap::<NodeIndex<GraphIdx>, usize>::new();
+        for node in reps_kept.into_iter().flatten() {
+            *nodes_freq.entry(node).or_default() += 1;
         }
-        n_iters += 1;
-
-        // Update progress bar
-        prune_span.pb_inc(delta_n_edges - graph.edge_count() as u64);
-        delta_n_edges = graph.edge_count() as u64;
-        if enabled!(Level::DEBUG) {
-            prune_span.pb_set_message(&*format!(
-                "from {0} nodes ({1:.2}/s) [{2} iters.]",
-                delta_n_nodes,
-                delta_n_nodes as f32 / prune_span.pb_elapsed().as_secs_f32(),
-                n_iters,
-            ));
+
+        info!("Saving keep frequency of nodes");
+        let mut freq_file = File::create(args.out_freq.expect("replicates require an output file"))
+            .expect("cannot open keep frequency file");
+        writeln!(freq_file, "node\tn_kept\tfreq").expect("cannot write to keep frequency file");
+        for node in graph.node_indices().sorted_by_key(|node| &graph[*node]) {
+            let n_kept = nodes_freq.get(&node).copied().unwrap_or(0);
+            writeln!(
+                freq_file,
+                "{0}\t{n_kept}\t{1}",
+                graph[node],
+                n_kept as f32 / n_replicates as f32
+            )
+            .expect("cannot write to keep frequency file");
         }
+        return;
     }
-    std::mem::drop(prune_span_enter);
-    std::mem::drop(prune_span);
 
-    info!("Pruning complete!");
-    debug!(
-        "Final graph has {0} nodes with {1} edges",
-        graph.node_count(),
-        graph.edge_count()
-    );
+    if args.jitter > 0.0 {
+        crate::graph::graph_jitter(&mut graph, args.jitter, args.seed.unwrap_or(0));
+    }
 
-    info!("Saving remaining nodes");
-    if args.out.is_some() {
-        let mut writer_file = File::create(args.out.unwrap()).expect("cannot open output file");
-        write(&mut writer_file, &mut graph.node_weights())
-            .expect("cannot write results to output file");
+    if sweep_filters.is_empty() {
+        let nodes_excl = crate::graph::graph_prune(
+            &mut graph,
+            args.mode,
+            args.keep_heavy,
+            &HashSet::new(),
+            args.seed,
+            &node_score,
+        );
+        info!("Pruning complete!");
+
+        info!("Saving remaining nodes");
+        if let Some(out) = args.out {
+            let mut writer_file = File::create(out).expect("cannot open output file");
+            write(&mut writer_file, &mut graph.node_weights())
+                .expect("cannot write results to output file");
+        } else {
+            write(&mut stdout().lock(), &mut graph.node_weights())
+                .expect("cannot write results to stdout");
+        }
+
+        if let Some(out_excl) = args.out_excl {
+            info!("Saving excluded nodes to file");
+            let mut writer_file =
+                File::create(out_excl).expect("cannot open output file for excluded nodes");
+            write(&mut writer_file, &mut nodes_excl.iter())
+                .expect("cannot write excluded nodes to file");
+        }
     } else {
-        write(&mut stdout().lock(), &mut graph.node_weights())
-            .expect("cannot write results to stdout");
-    }
+        info!("Running {} sweep(s)", sweep_filters.len());
+        let out = args.out.expect("sweep requires an output file");
+        let run_sweep = |sweep_idx: usize, nodes_keep: &HashSet<NodeIndex<GraphIdx>>| {
+            let mut graph_sweep = graph.clone();
+            crate::graph::graph_sweep(&mut graph_sweep, &edges_sweep, sweep_idx);
+            let nodes_excl = crate::graph::graph_prune(
+                &mut graph_sweep,
+                args.mode,
+                args.keep_heavy,
+                nodes_keep,
+                args.seed,
+                &node_score,
+            );
+            info!(
+                "Pruning complete for '{0}': kept {1} of {2} nodes",
+                sweep_filters[sweep_idx],
+                graph_sweep.node_count(),
+                graph.node_count()
+            );
+
+            let suffix = sweep_suffix(&args.sweep[sweep_idx]);
+            let mut writer_file =
+                File::create(suffix_path(&out, &suffix)).expect("cannot open sweep output file");
+            write(&mut writer_file, &mut graph_sweep.node_weights())
+                .expect("cannot write results to sweep output file");
+            if let Some(out_excl) = &args.out_excl {
+                let mut writer_file = File::create(suffix_path(out_excl, &suffix))
+                    .expect("cannot open sweep output file for excluded nodes");
+                write(&mut writer_file, &mut nodes_excl.iter())
+                    .expect("cannot write excluded nodes to sweep file");
+            }
 
-    if args.out_excl.is_some() {
-        info!("Saving excluded nodes to file");
-        let mut writer_file = File::create(args.out_excl.unwrap())
-            .expect("cannot open output file for excluded nodes");
-        write(&mut writer_file, &mut sorted(nodes_excl))
-            .expect("cannot write excluded nodes to file");
+            graph_sweep
+                .node_indices()
+                .collect::<HashSet<NodeIndex<GraphIdx>>>()
+        };
+
+        let sweep_kept: Vec<HashSet<NodeIndex<GraphIdx>>> = if args.sweep_nested {
+            // Each sweep keeps the nodes kept on the previous (stricter) one
+            let mut sweep_kept = vec![HashSet::new(); sweep_filters.len()];
+            let mut nodes_keep = HashSet::new();
+            let order = crate::graph::sweep_order(&edges_sweep, sweep_filters.len());
+            for sweep_idx in &order {
+                nodes_keep = run_sweep(*sweep_idx, &nodes_keep);
+                sweep_kept[*sweep_idx] = nodes_keep.clone();
+            }
+            let nodes_sweep = crate::graph::sweep_strictest(&sweep_kept, &order);
+
+            info!("Saving nested sweep table");
+            let mut nested_file = File::create(suffix_path(&out, "nested.tsv"))
+                .expect("cannot open nested sweep file");
+            writeln!(nested_file, "node\tsweep").expect("cannot write to nested sweep file");
+            for node in graph.node_indices().sorted_by_key(|node| &graph[*node]) {
+                writeln!(
+                    nested_file,
+                    "{0}\t{1}",
+                    graph[node],
+                    nodes_sweep
+                        .get(&node)
+                        .map_or("NA", |sweep_idx| args.sweep[*sweep_idx].as_str())
+                )
+                .expect("cannot write to nested sweep file");
+            }
+
+            sweep_kept
+        } else {
+            (0..sweep_filters.len())
+                .into_par_iter()
+                .map(|sweep_idx| run_sweep(sweep_idx, &HashSet::new()))
+                .collect()
+        };
+
+        info!("Saving sweep summary");
+        let mut summary_file =
+            File::create(suffix_path(&out, "sweep.tsv")).expect("cannot open sweep summary file");
+        writeln!(summary_file, "sweep\tfilter\tn_nodes\tn_kept\tn_excl")
+            .expect("cannot write to sweep summary file");
+        for ((sweep, sweep_filter), nodes_kept) in
+            args.sweep.iter().zip(&sweep_filters).zip(sweep_kept)
+        {
+            writeln!(
+                summary_file,
+                "{sweep}\t{sweep_filter}\t{0}\t{1}\t{2}",
+                graph.node_count(),
+                nodes_kept.len(),
+                graph.node_count() - nodes_kept.len()
+            )
+            .expect("cannot write to sweep summary file");
+        }
     }
 
     info!(
@@ -283,3 +549,25 @@ where
 
     Ok(())
 }
+
+/// Append `suffix` to a file path (e.g. "out.keep" => "out.keep.0.2").
+fn suffix_path(path: &Path, suffix: &str) -> PathBuf {
+    let mut path = path.as_os_str().to_owned();
+    path.push(".");
+    path.push(suffix);
+    PathBuf::from(path)
+}
+
+/// File-name friendly version of a sweep threshold/filter.
+fn sweep_suffix(sweep: &str) -> String {
+    sweep
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '.' || c == '-' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
@@ -0,0 +1,411 @@
+use crate::graph::{Delimiter, Graph};
+use petgraph::visit::{EdgeRef, IntoEdgeReferences, NodeIndexable};
+use std::{
+    fs::File,
+    io::{BufRead, BufWriter, Read, Write},
+    path::Path,
+};
+use tracing::{debug, error, info};
+
+/// Header of the edges read from a matrix (its cells are used as the "r2" column).
+const MATRIX_HEADER: [&str; 3] = ["site1", "site2", "r2"];
+
+/// Reader of square or lower-triangular LD matrices (one row per site) as delimited edges (with header).
+///
+/// Edges are output with the same delimiter as the matrix (tab, if whitespace or auto-detected per row). The matrix is streamed row by row, and each pair of sites is output once (from the lower triangle, without the diagonal), so that the weight filters are applied to each cell before it is added to the graph.
+pub struct MatrixReader<R> {
+    inner: R,
+    sites: Vec<String>,
+    delimiter: Delimiter,
+    sep: char,
+    row: usize,
+    line: String,
+    buf: Vec<u8>,
+    pos: usize,
+}
+
+impl<R: BufRead> MatrixReader<R> {
+    pub fn new(inner: R, sites: Vec<String>, delimiter: Delimiter) -> Self {
+        let sep = match delimiter {
+            Delimiter::Char(c) => c,
+            Delimiter::Auto | Delimiter::Whitespace => '\t',
+        };
+        Self {
+            inner,
+            sites,
+            delimiter,
+            sep,
+            row: 0,
+            line: String::new(),
+            buf: format!("{}\n", MATRIX_HEADER.join(&sep.to_string())).into_bytes(),
+            pos: 0,
+        }
+    }
+
+    /// Format the next matrix row into the buffer, returning the number of rows read.
+    fn fill(&mut self) -> std::io::Result<usize> {
+        loop {
+            self.line.clear();
+            if self.inner.read_line(&mut self.line)? == 0 {
+                if self.row != self.sites.len() {
+                    error!(
+                        "LD matrix has {} row(s), but {} sites were provided",
+                        self.row,
+                        self.sites.len()
+                    );
+                    std::process::exit(-1);
+                }
+                return Ok(0);
+            }
+            if !self.line.starts_with('#') {
+                break;
+            }
+        }
+
+        let line = self.line.trim_end_matches(['\n', '\r']);
+        let cells = if line.is_empty() {
+            Vec::new()
+        } else {
+            self.delimiter.split(line)
+        };
+        let row = self.row;
+        if row >= self.sites.len() {
+            error!(
+                "LD matrix has more rows than the {} sites provided",
+                self.sites.len()
+            );
+            std::process::exit(-1);
+        }
+        if cells.len() < row || cells.len() > self.sites.len() {
+            error!(
+                "row {} of LD matrix has {} cell(s), but should have between {row} and {} (lower-triangular or square)",
+                row + 1,
+                cells.len(),
+                self.sites.len()
+            );
+            std::process::exit(-1);
+        }
+
+        self.buf.clear();
+        self.pos = 0;
+        for (site, cell) in self.sites[..row].iter().zip(&cells) {
+            writeln!(self.buf, "{site}{0}{1}{0}{cell}", self.sep, self.sites[row])?;
+        }
+        self.row += 1;
+        Ok(1)
+    }
+}
+
+impl<R: BufRead> Read for MatrixReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let data = self.fill_buf()?;
+        let n = buf.len().min(data.len());
+        buf[..n].copy_from_slice(&data[..n]);
+        self.consume(n);
+        Ok(n)
+    }
+}
+
+impl<R: BufRead> BufRead for MatrixReader<R> {
+    fn fill_buf(&mut self) -> std::io::Result<&[u8]> {
+        while self.pos == self.buf.len() {
+            if self.fill()? == 0 {
+                break;
+            }
+        }
+        Ok(&self.buf[self.pos..])
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.pos += amt;
+    }
+}
+
+/// Reader of MatrixMarket coordinate files (e.g. from scipy or R's Matrix package) as delimited edges (with header).
+///
+/// Rows and columns are labelled with the given sites, and each stored entry (except for the diagonal) is output as an edge; entries can be "real", "integer" or "pattern" (weight 1).
+/// Matrices must be "symmetric" (lower triangle stored) or "general", of which only the lower triangle is read (so that each pair of sites is only read once).
+pub struct MtxReader<R> {
+    inner: R,
+    sites: Vec<String>,
+    pattern: bool,
+    /// Whether the matrix is "general" (only its lower triangle is read)
+    general: bool,
+    /// Number of entries skipped from the upper triangle (of a "general" matrix)
+    n_upper: usize,
+    n_entries: Option<usize>,
+    n_read: usize,
+    line: String,
+    buf: Vec<u8>,
+    pos: usize,
+}
+
+impl<R: BufRead> MtxReader<R> {
+    pub fn new(mut inner: R, sites: Vec<String>) -> Self {
+        let mut banner = String::new();
+        inner
+            .read_line(&mut banner)
+            .expect("cannot read line from MatrixMarket file");
+        let banner: Vec<String> = banner.split_whitespace().map(str::to_lowercase).collect();
+        let (pattern, general) = match banner.iter().map(String::as_str).collect::<Vec<&str>>()[..]
+        {
+            ["%%matrixmarket", "matrix", "coordinate", field @ ("real" | "integer" | "pattern"), symmetry @ ("general" | "symmetric")] => {
+                (field == "pattern", symmetry == "general")
+            }
+            _ => {
+                error!(
+                    "unsupported MatrixMarket file (only real, integer or pattern coordinate matrices, either general or symmetric, are supported): {}",
+                    banner.join(" ")
+                );
+                std::process::exit(-1);
+            }
+        };
+        Self {
+            inner,
+            sites,
+            pattern,
+            general,
+            n_upper: 0,
+            n_entries: None,
+            n_read: 0,
+            line: String::new(),
+            buf: format!("{}\n", MATRIX_HEADER.join("\t")).into_bytes(),
+            pos: 0,
+        }
+    }
+
+    /// Format the next entries into the buffer, returning the number of lines read.
+    fn fill(&mut self) -> std::io::Result<usize> {
+        self.buf.clear();
+        self.pos = 0;
+        let mut n_lines = 0;
+        while n_lines < 1024 {
+            self.line.clear();
+            if self.inner.read_line(&mut self.line)? == 0 {
+                if n_lines == 0 && self.n_entries != Some(self.n_read) {
+                    error!(
+                        "MatrixMarket file has {} entries, but {} were expected",
+                        self.n_read,
+                        self.n_entries.unwrap_or_default()
+                    );
+                    std::process::exit(-1);
+                }
+                let n_upper = std::mem::take(&mut self.n_upper);
+                if n_lines == 0 && n_upper > 0 {
+                    debug!("Skipped {n_upper} entries from the upper triangle of the MatrixMarket file");
+                    if n_upper == self.n_read {
+                        error!("general MatrixMarket file only has entries in its upper triangle (only the lower triangle is read)");
+                        std::process::exit(-1);
+                    }
+                }
+                break;
+            }
+            n_lines += 1;
+            if self.line.starts_with('%') || self.line.trim().is_empty() {
+                continue;
+            }
+            let fields: Vec<&str> = self.line.split_whitespace().collect();
+
+            // Size line (rows, columns and number of entries)
+            let Some(n_entries) = self.n_entries else {
+                let size: Vec<usize> = fields.iter().filter_map(|f| f.parse().ok()).collect();
+                match size[..] {
+                    [rows, cols, n]
+                        if fields.len() == 3 && rows == cols && rows == self.sites.len() =>
+                    {
+                        self.n_entries = Some(n)
+                    }
+                    _ => {
+                        error!(
+                            "invalid MatrixMarket size line (should be a square matrix with {} rows/columns): {}",
+                            self.sites.len(),
+                            self.line.trim_end()
+                        );
+                        std::process::exit(-1);
+                    }
+                }
+                continue;
+            };
+
+            // Entries
+            self.n_read += 1;
+            if self.n_read > n_entries || fields.len() != 3 - self.pattern as usize {
+                error!("invalid MatrixMarket entry: {}", self.line.trim_end());
+                std::process::exit(-1);
+            }
+            let (row, col) = (
+                mtx_index(&self.sites, fields[0]),
+                mtx_index(&self.sites, fields[1]),
+            );
+            if row == col {
+                continue;
+            }
+            if self.general && row < col {
+                self.n_upper += 1;
+                continue;
+            }
+            let weight = if self.pattern { "1" } else { fields[2] };
+            writeln!(
+                self.buf,
+                "{}\t{}\t{weight}",
+                self.sites[col], self.sites[row]
+            )?;
+        }
+        Ok(n_lines)
+    }
+}
+
+/// Index of the site of a (1-based) MatrixMarket row/column index.
+fn mtx_index(sites: &[String], index: &str) -> usize {
+    match index.parse::<usize>() {
+        Ok(i) if i >= 1 && i <= sites.len() => i - 1,
+        _ => {
+            error!(
+                "invalid index '{index}' in MatrixMarket file (with {} sites)",
+                sites.len()
+            );
+            std::process::exit(-1);
+        }
+    }
+}
+
+impl<R: BufRead> Read for MtxReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let data = self.fill_buf()?;
+        let n = buf.len().min(data.len());
+        buf[..n].copy_from_slice(&data[..n]);
+        self.consume(n);
+        Ok(n)
+    }
+}
+
+impl<R: BufRead> BufRead for MtxReader<R> {
+    fn fill_buf(&mut self) -> std::io::Result<&[u8]> {
+        while self.pos == self.buf.len() {
+            if self.fill()? == 0 {
+                break;
+            }
+        }
+        Ok(&self.buf[self.pos..])
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.pos += amt;
+    }
+}
+
+/// Write graph as a MatrixMarket (symmetric, lower-triangle) coordinate file, and its node IDs (one per line, in row order) to "<path>.sites".
+pub fn graph_mtx_write(graph: &Graph, path: &Path) {
+    let mut sites_path = path.as_os_str().to_owned();
+    sites_path.push(".sites");
+    info!(
+        "Writing graph as MatrixMarket file {:?} (sites to {:?})",
+        path, sites_path
+    );
+    let mut mtx = BufWriter::new(File::create(path).expect("cannot create MatrixMarket file"));
+    let mut sites = BufWriter::new(File::create(sites_path).expect("cannot create sites file"));
+    mtx_write(graph, &mut mtx, &mut sites).expect("cannot write to MatrixMarket file");
+}
+
+fn mtx_write<W: Write>(graph: &Graph, mtx: &mut W, sites: &mut W) -> std::io::Result<()> {
+    // Rows are numbered in index order (node indices may have holes, e.g. after subsetting)
+    let mut nodes_row = vec![0; graph.node_bound()];
+    for (row, node) in graph.node_indices().enumerate() {
+        nodes_row[node.index()] = row + 1;
+        writeln!(sites, "{}", graph[node])?;
+    }
+
+    writeln!(mtx, "%%MatrixMarket matrix coordinate real symmetric")?;
+    writeln!(
+        mtx,
+        "% written by prune_graph v{}",
+        env!("CARGO_PKG_VERSION")
+    )?;
+    writeln!(mtx, "{0} {0} {1}", graph.node_count(), graph.edge_count())?;
+    for edge in graph.edge_references() {
+        let (a, b) = (
+            nodes_row[edge.source().index()],
+            nodes_row[edge.target().index()],
+        );
+        writeln!(mtx, "{} {} {}", a.max(b), a.min(b), edge.weight())?;
+    }
+    mtx.flush()?;
+    sites.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::graph::{graph_read, InputFormat, ReadOpts};
+
+    #[test]
+    fn test_matrix_reader() {
+        let sites: Vec<String> = ["A", "B", "C", "D"].map(String::from).to_vec();
+        let square = "1 0.5 0.1 0\n0.5 1 0.3 nan\n0.1 0.3 1 0.9\n0 nan 0.9 1\n";
+        let triangle = "1\n0.5\t1\n0.1\t0.3\t1\n0\tnan\t0.9\t1\n";
+
+        for matrix in [square, triangle] {
+            let mut reader = MatrixReader::new(matrix.as_bytes(), sites.clone(), Delimiter::Auto);
+            let mut edges = String::new();
+            reader.read_to_string(&mut edges).unwrap();
+            assert_eq!(
+                edges,
+                "site1\tsite2\tr2\nA\tB\t0.5\nA\tC\t0.1\nB\tC\t0.3\nA\tD\t0\nB\tD\tnan\nC\tD\t0.9\n"
+            );
+
+            let opts = InputFormat::Matrix.read_opts(ReadOpts {
+                weight_field: InputFormat::Matrix.weight_field().to_string(),
+                weight_filter: Some("r2 > 0.2".to_string()),
+                ..Default::default()
+            });
+            let reader = MatrixReader::new(matrix.as_bytes(), sites.clone(), Delimiter::Auto);
+            let (graph, graph_idx, _edges_sweep, _meta) = graph_read(reader, &opts);
+            assert_eq!(graph.node_count(), 4);
+            assert_eq!(graph.edge_count(), 3);
+            let edge = graph.find_edge(graph_idx["D"], graph_idx["C"]).unwrap();
+            assert_eq!(graph[edge], 0.9);
+        }
+    }
+
+    #[test]
+    fn test_mtx() {
+        let sites: Vec<String> = ["A", "B", "C", "D"].map(String::from).to_vec();
+        let mtx = "%%MatrixMarket matrix coordinate real symmetric\n% comment\n4 4 4\n2 1 0.5\n3 3 1\n4 3 0.9\n3 2 0.25\n";
+        let mut reader = MtxReader::new(mtx.as_bytes(), sites.clone());
+        let mut edges = String::new();
+        reader.read_to_string(&mut edges).unwrap();
+        assert_eq!(
+            edges,
+            "site1\tsite2\tr2\nA\tB\t0.5\nC\tD\t0.9\nB\tC\t0.25\n"
+        );
+
+        // Only the lower triangle of a general matrix is read
+        let general = "%%MatrixMarket matrix coordinate real general\n4 4 4\n2 1 0.5\n1 2 0.5\n4 3 0.9\n3 4 0.9\n";
+        let mut reader = MtxReader::new(general.as_bytes(), sites.clone());
+        let mut edges = String::new();
+        reader.read_to_string(&mut edges).unwrap();
+        assert_eq!(edges, "site1\tsite2\tr2\nA\tB\t0.5\nC\tD\t0.9\n");
+
+        // Round-trip
+        let opts = InputFormat::Mtx.read_opts(ReadOpts {
+            weight_field: InputFormat::Mtx.weight_field().to_string(),
+            ..Default::default()
+        });
+        let (graph, _graph_idx, _edges_sweep, _meta) =
+            graph_read(MtxReader::new(mtx.as_bytes(), sites.clone()), &opts);
+        let (mut mtx_out, mut sites_out) = (Vec::new(), Vec::new());
+        mtx_write(&graph, &mut mtx_out, &mut sites_out).unwrap();
+        let sites_out: Vec<String> = String::from_utf8(sites_out)
+            .unwrap()
+            .lines()
+            .map(str::to_string)
+            .collect();
+        assert_eq!(sites_out, ["A", "B", "C", "D"]);
+        let (graph_out, graph_idx, _edges_sweep, _meta) =
+            graph_read(MtxReader::new(mtx_out.as_slice(), sites_out), &opts);
+        assert_eq!(graph_out.node_count(), 4);
+        assert_eq!(graph_out.edge_count(), 3);
+        let edge = graph_out.find_edge(graph_idx["B"], graph_idx["C"]).unwrap();
+        assert_eq!(graph_out[edge], 0.25);
+    }
+}
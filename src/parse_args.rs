@@ -1,3 +1,7 @@
+use crate::graph::{
+    Delimiter, DupPolicy, InputFormat, InvalidPolicy, LayerCombine, MissingPolicy, NegativePolicy,
+    NonFinitePolicy, Preset, SelfLoopPolicy,
+};
 use clap::{ArgAction, Parser};
 use std::path::PathBuf;
 
@@ -9,16 +13,101 @@ pub struct Args {
     #[clap(short, long, default_value_t = 1, value_name = "INT")]
     pub n_threads: usize,
 
-    /// Input file.
+    /// Input file(s).
     ///
-    /// File with edges to be pruned.
-    #[clap(short, long = "in", value_name = "FILE")]
-    pub input: Option<PathBuf>,
+    /// File(s) with edges to be pruned (read from STDIN if none); several files (or glob patterns, e.g. "ld/chr*.tsv.gz") are merged into a single graph.
+    #[clap(short, long = "in", num_args = 1.., value_name = "FILE")]
+    pub input: Vec<PathBuf>,
+
+    /// Load graph from cache.
+    ///
+    /// Load the graph (already filtered) from a binary cache file created with '--save-graph', instead of reading the input file(s); it can be filtered further with '--weight-filter', which can then only use the weight field (i.e. the cached edge weight).
+    #[clap(long, conflicts_with_all = ["input", "layers", "weight_expr"], value_name = "FILE")]
+    pub load_graph: Option<PathBuf>,
+
+    /// Save graph to cache.
+    ///
+    /// Save the graph (after reading and filtering the input) to a binary cache file, to be loaded with '--load-graph' on later runs; only node IDs, edge weights, sweep filters, node positions, the input header and the weight and filters used are stored (other input columns cannot be used after loading it).
+    #[clap(long, value_name = "FILE")]
+    pub save_graph: Option<PathBuf>,
+
+    /// Input files as layers.
+    ///
+    /// Read each input file as a separate layer (e.g. LD from different populations) with the same node IDs and header, and combine their edge weights (see '--layer-combine') before pruning; layers are read unfiltered, and filters (which can only use the weight field) are applied to the combined weight. Duplicated edges within a layer are merged (see '--duplicates').
+    #[clap(long, requires = "input", conflicts_with = "sweep")]
+    pub layers: bool,
+
+    /// Layer combination.
+    ///
+    /// How to combine each edge's weights across layers.
+    #[clap(long, value_enum, default_value_t = LayerCombine::Max, requires = "layers", value_name = "FUNCTION")]
+    pub layer_combine: LayerCombine,
+
+    /// Layer sample sizes.
+    ///
+    /// Sample size of each layer (in the same order as the input files), for '--layer-combine weighted-mean'.
+    #[clap(long, value_delimiter = ',', requires = "layers", value_name = "FLOAT")]
+    pub layer_sizes: Vec<f64>,
+
+    /// Missing edge weight.
+    ///
+    /// Weight of edges absent from a layer (e.g. 0); by default, absent edges are ignored when combining that edge's weights.
+    #[clap(long, requires = "layers", value_name = "FLOAT")]
+    pub layer_missing: Option<f32>,
+
+    /// Input preset.
+    ///
+    /// Configure header, node columns, weight field and filter for the output of common LD tools ("ngsld": header, "site1" and "site2" as nodes, "r2" as weight and "r2 > 0.2" as filter; "plink": PLINK format, "R2" as weight and "R2 > 0.2" as filter); options set explicitly take precedence.
+    #[clap(long, value_enum, required = false, value_name = "PRESET")]
+    pub preset: Option<Preset>,
+
+    /// Input format.
+    ///
+    /// Format of the input file; "plink" reads PLINK '--r2' output directly (header, whitespace delimiter, "SNP_A" and "SNP_B" as nodes and "R2" as default weight); "arrow" and "parquet" read Apache Arrow IPC and Parquet files (decoding only the columns used); "matrix" reads a square or lower-triangular LD matrix and "mtx" a MatrixMarket coordinate file (see '--matrix-sites') [default: detect from file extension].
+    #[clap(long, value_enum, default_value_t = InputFormat::Auto, value_name = "FORMAT")]
+    pub format: InputFormat,
+
+    /// LD matrix sites.
+    ///
+    /// File with the sites of the rows/columns of an LD matrix (for '--format matrix' or '--format mtx'), one per line (or a PLINK '.bim' file), in the same order as the matrix.
+    #[clap(long, value_name = "FILE")]
+    pub matrix_sites: Option<PathBuf>,
 
     /// Input file has header.
+    ///
+    /// Input file has a header (also if prefixed by '#', e.g. "#CHROM"); if neither this nor '--columns' is provided, a header is detected from the first line (if it has any of the columns used, or a non-numeric weight value).
     #[clap(long, action)]
     pub header: bool,
 
+    /// Column names.
+    ///
+    /// Names of the columns of an input file without header (e.g. "site1,site2,dist,r2"), to be used in node fields, weight field and filters.
+    #[clap(
+        long,
+        value_delimiter = ',',
+        value_name = "STRING",
+        conflicts_with = "header"
+    )]
+    pub columns: Option<Vec<String>>,
+
+    /// Field delimiter.
+    ///
+    /// Delimiter of input file fields: "tab", "comma", "whitespace" (any run of spaces or tabs), a single character or "auto" (detect from first line).
+    #[clap(long, default_value = "auto", value_name = "STRING")]
+    pub delimiter: Delimiter,
+
+    /// Node ID columns.
+    ///
+    /// Columns in input file with the IDs of the two nodes of each edge (default: first two columns); composite IDs can be built from several columns separated by ':' (e.g. "CHR_A:BP_A,CHR_B:BP_B").
+    #[clap(long, value_delimiter = ',', value_name = "STRING")]
+    pub node_fields: Option<Vec<String>>,
+
+    /// Node universe.
+    ///
+    /// File with all nodes (e.g. sites), so that nodes without any edges are kept (and output); either a list of node IDs (one per line), a '.pos' file (chromosome and position, with "chr:pos" as ID) or a PLINK '.bim' file [default: '--matrix-sites', for LD matrix input].
+    #[clap(long, value_name = "FILE")]
+    pub nodes: Option<PathBuf>,
+
     /// Node IDs to exclude.
     ///
     /// File with node IDs to include (one per line).
@@ -27,32 +116,144 @@ pub struct Args {
 
     /// Weight column.
     ///
-    /// Column in input file to use as weight (needs to be present in header); if input file has no header you can use "column_#", where "#" stands for the column number.
-    #[clap(short = 'w', long, default_value = "column_3", value_name = "STRING")]
-    pub weight_field: String,
+    /// Column in input file to use as weight (needs to be present in header); if input file has no header you can use "column_#", where "#" stands for the column number [default: "column_3", "R2" for PLINK format or "r2" for LD matrices].
+    #[clap(short = 'w', long, value_name = "STRING")]
+    pub weight_field: Option<String>,
+
+    /// Weight expression.
+    ///
+    /// Expression to calculate each edge's weight from the input columns (instead of using '--weight-field'); any expression supported by 'fasteval' (e.g. "abs(D)" or "r2 * (dist < 50000)").
+    #[clap(long, required = false, value_name = "STRING")]
+    pub weight_expr: Option<String>,
 
     /// Filter expression.
     ///
-    /// Expression to filter edges before pruning; any expression supported by 'fasteval'.
+    /// Expression to filter edges before pruning; any expression supported by 'fasteval', plus (in)equality comparisons of non-numeric columns and quoted strings (e.g. "type != 'indel'").
     #[clap(short = 'f', long, required = false, value_name = "STRING")]
     pub weight_filter: Option<String>,
 
     /// Weight as number of edges.
     ///
-    /// Node's weight as number of connected edges, instead of (default) summing over their weights.
-    #[clap(long)]
+    /// Node's weight as number of connected edges, instead of (default) summing over their weights (same as '--node-score count').
+    #[clap(long, conflicts_with = "node_score")]
     pub weight_n_edges: bool,
 
+    /// Node score expression.
+    ///
+    /// Expression to calculate each node's weight; any expression supported by 'fasteval' over the aggregates of its edges' weights ("sum", "max", "mean", "count" and "sumsq") and node attributes (if provided).
+    #[clap(long, default_value = "sum", value_name = "STRING")]
+    pub node_score: String,
+
+    /// Node attributes.
+    ///
+    /// TSV file (with header) with node IDs on the first column and numeric node attributes on the others; attributes can be used in the node score and (as "node1.<attr>" and "node2.<attr>") in edge filters.
+    #[clap(long, required = false, value_name = "FILE")]
+    pub node_attrs: Option<PathBuf>,
+
+    /// Duplicated edges.
+    ///
+    /// How to handle edges present more than once in the input (in any direction); except for "keep", duplicates are merged before filtering, so filters apply to the merged edge (with "mean" and "sum", numeric columns are merged as well, and the weight expression is evaluated on them).
+    #[clap(long, value_enum, default_value_t = DupPolicy::Keep, value_name = "POLICY")]
+    pub duplicates: DupPolicy,
+
+    /// Self-loops.
+    ///
+    /// How to handle edges from a node to itself.
+    #[clap(long, value_enum, default_value_t = SelfLoopPolicy::Skip, value_name = "POLICY")]
+    pub self_loops: SelfLoopPolicy,
+
+    /// Negative weights.
+    ///
+    /// How to handle edges with negative weights (e.g. when using 'D' as weight).
+    #[clap(long, value_enum, default_value_t = NegativePolicy::Keep, value_name = "POLICY")]
+    pub negative: NegativePolicy,
+
+    /// Non-finite values.
+    ///
+    /// How to handle NaN and infinite values in the columns used by the weight or filters.
+    #[clap(long, value_enum, default_value_t = NonFinitePolicy::Keep, value_name = "POLICY")]
+    pub non_finite: NonFinitePolicy,
+
+    /// Cap for infinite values.
+    ///
+    /// Value to replace infinite values with (keeping their sign), if '--non-finite cap'.
+    #[clap(long, default_value_t = 1.0, value_name = "FLOAT")]
+    pub non_finite_cap: f64,
+
+    /// Missing values.
+    ///
+    /// Values that stand for missing data (e.g. "NA"), in the columns used by the weight or filters; an empty value (e.g. "NA,") stands for empty fields.
+    #[clap(
+        long,
+        value_delimiter = ',',
+        default_value = "NA,",
+        value_name = "STRING"
+    )]
+    pub missing_values: Vec<String>,
+
+    /// Rows with missing values.
+    ///
+    /// How to handle rows with missing values (see '--missing-values').
+    #[clap(long, value_enum, default_value_t = MissingPolicy::Skip, value_name = "POLICY")]
+    pub missing: MissingPolicy,
+
+    /// Invalid rows.
+    ///
+    /// How to handle rows that cannot be parsed (wrong number of fields or non-numeric weight values).
+    #[clap(long, value_enum, default_value_t = InvalidPolicy::Error, value_name = "POLICY")]
+    pub invalid: InvalidPolicy,
+
+    /// Maximum number of warnings.
+    ///
+    /// Maximum number of skipped rows to log a warning for (the number of skipped rows per reason is always reported).
+    #[clap(long, default_value_t = 10, value_name = "INT")]
+    pub max_warnings: usize,
+
     /// Weight precision.
     #[clap(long, default_value_t = 4, value_name = "INT")]
     pub weight_precision: u8,
 
+    /// Sweep thresholds/filters.
+    ///
+    /// Prune the graph once per threshold (edges with weight above it) or filter expression, reading the input only once; outputs are written with the threshold/filter as suffix, plus a summary table ("<out>.sweep.tsv").
+    #[clap(long, num_args = 1.., requires = "out", value_name = "FLOAT|STRING")]
+    pub sweep: Vec<String>,
+
+    /// Nested sweeps.
+    ///
+    /// Run sweeps from strictest (most edges) to loosest, keeping all nodes kept on the previous sweep, so that kept sets are nested; the strictest sweep at which each node is kept (i.e. first kept) is written to "<out>.nested.tsv".
+    #[clap(long, requires = "sweep")]
+    pub sweep_nested: bool,
+
     /// Keep 'heavy' nodes
     ///
     /// Keep 'heavy' (highest total weight) nodes, instead of (default) removing them.
     #[clap(long, action)]
     pub keep_heavy: bool,
 
+    /// Random seed.
+    ///
+    /// Break ties between equally heavy nodes randomly (with this seed), instead of (default) alphabetically.
+    #[clap(long, required = false, value_name = "INT")]
+    pub seed: Option<u64>,
+
+    /// Weight jitter.
+    ///
+    /// Add uniform noise (between -FLOAT and FLOAT) to edge weights before pruning. The noise is drawn with the seed from '--seed' (or 0, if not set), so runs are reproducible.
+    #[clap(long, default_value_t = 0.0, value_name = "FLOAT")]
+    pub jitter: f32,
+
+    /// Number of replicates.
+    ///
+    /// Prune the graph INT times (with random tie-breaking and jitter, using seeds "seed", "seed+1", ..., with "seed" 0 if '--seed' is not set) and output each node's keep frequency (instead of the pruned nodes).
+    #[clap(
+        long,
+        requires = "out_freq",
+        conflicts_with_all = ["sweep", "out", "out_excl"],
+        value_name = "INT"
+    )]
+    pub replicates: Option<usize>,
+
     /// Prunning mode.
     #[clap(long, default_value_t = 1, value_name = "INT")]
     pub mode: u8,
@@ -63,6 +264,12 @@ pub struct Args {
     #[clap(long, required = false, value_name = "FILE")]
     pub out_graph: Option<PathBuf>,
 
+    /// Output starting graph in MatrixMarket format.
+    ///
+    /// The file to output starting graph as a MatrixMarket coordinate file (e.g. for scipy or R's Matrix package), with node IDs (in row order) written to "<FILE>.sites".
+    #[clap(long, required = false, value_name = "FILE")]
+    pub out_mtx: Option<PathBuf>,
+
     /// Output starting components in JSONL format.
     ///
     /// The file to output the starting components in JSONL format.
@@ -75,6 +282,12 @@ pub struct Args {
     #[clap(long, required = false, value_name = "FILE")]
     pub out_excl: Option<PathBuf>,
 
+    /// Keep frequency file.
+    ///
+    /// File to output each node's keep frequency across replicates.
+    #[clap(long, required = false, requires = "replicates", value_name = "FILE")]
+    pub out_freq: Option<PathBuf>,
+
     /// Output file.
     ///
     /// The file to output pruned nodes.